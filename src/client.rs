@@ -1,39 +1,145 @@
-use crate::server;
+use crate::server::{self, FrameBuffer};
 use crate::{Error, Result};
 
-use bincode::serialize;
+use serde_json::json;
 
 use std::io::prelude::*;
-use std::io::{self, Write};
+use std::io::{self, ErrorKind};
 use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-fn send_one_request(request: &server::Request) -> Result<()> {
-    let mut socket = UnixStream::connect(server::SOCKET_PATH)
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reads from a socket while remembering whether a read timed out.
+struct TimeoutReader<'a> {
+    socket: &'a mut UnixStream,
+    timed_out: bool,
+}
+
+impl Read for TimeoutReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.socket.read(buf);
+        if let Err(ref err) = result {
+            if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut {
+                self.timed_out = true;
+            }
+        }
+        result
+    }
+}
+
+fn connect(socket_path: &Path, request_kind: &str) -> Result<(UnixStream, FrameBuffer)> {
+    let socket =
+        UnixStream::connect(socket_path).map_err(|err| Error::new("connecting to server", &err))?;
+    handshake(socket, request_kind)
+}
+
+fn handshake(mut socket: UnixStream, request_kind: &str) -> Result<(UnixStream, FrameBuffer)> {
+    socket
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(|err| Error::new("connecting to server", &err))?;
+    let mut buffer = FrameBuffer::new();
+    let mut reader = TimeoutReader {
+        socket: &mut socket,
+        timed_out: false,
+    };
+    // Daemons older than the handshake wait for a request without sending anything.
+    let frame = match buffer.read_frame(&mut reader) {
+        Ok(frame) => frame,
+        Err(_) if reader.timed_out => None,
+        Err(err) => return Err(err),
+    };
+    let hello = frame.and_then(|frame| bincode::deserialize::<server::Hello>(&frame).ok());
+    let hello = match hello {
+        Some(hello) => hello,
+        None => {
+            return Err(Error::from_description(
+                "connecting to server",
+                format!(
+                    "The daemon did not identify itself and is likely an older version than the \
+                     client ({}). Restart it with `robar stop && robar start`.",
+                    env!("CARGO_PKG_VERSION"),
+                ),
+            ))
+        }
+    };
+    socket
+        .set_read_timeout(None)
         .map_err(|err| Error::new("connecting to server", &err))?;
+
+    if hello.protocol_version != server::PROTOCOL_VERSION && request_kind != "stop" {
+        return Err(Error::from_description(
+            "connecting to server",
+            format!(
+                "The daemon is version {} (protocol {}), but the client is version {} (protocol \
+                 {}). Restart it with `robar stop && robar start`.",
+                hello.version,
+                hello.protocol_version,
+                env!("CARGO_PKG_VERSION"),
+                server::PROTOCOL_VERSION,
+            ),
+        ));
+    }
+    if !hello.requests.iter().any(|kind| kind == request_kind) {
+        return Err(Error::from_description(
+            "connecting to server",
+            format!(
+                "The daemon (version {}) does not support `{}` requests.",
+                hello.version, request_kind,
+            ),
+        ));
+    }
+
+    Ok((socket, buffer))
+}
+
+fn send_one_request(socket_path: &Path, request: &server::Request, wait: bool) -> Result<()> {
+    let (mut socket, mut buffer) = connect(socket_path, request.kind())?;
     send_request(request, &mut socket)?;
     socket
         .shutdown(Shutdown::Write)
-        .map_err(|err| Error::new("shutting down connection", &err))
+        .map_err(|err| Error::new("shutting down connection", &err))?;
+    if !wait {
+        return Ok(());
+    }
+    receive_response(&mut socket, &mut buffer).map(|_| ())
+}
+
+fn receive_response(socket: &mut UnixStream, buffer: &mut FrameBuffer) -> Result<server::Response> {
+    match server::read_message(buffer, socket)? {
+        Some(server::Response::Error(err)) => Err(err),
+        Some(response) => Ok(response),
+        None => Err(Error::from_description(
+            "receiving response",
+            "The daemon closed the connection without responding.",
+        )),
+    }
 }
 
 fn send_request(request: &server::Request, socket: &mut UnixStream) -> Result<()> {
-    let mut serialized_request =
-        serialize(&request).map_err(|err| Error::new("serializing request", &err))?;
-    serialized_request.push(server::END_OF_REQUEST_SEPARATOR);
-    socket
-        .write_all(&serialized_request)
-        .map_err(|err| Error::new("sending request", &err))?;
-    socket
-        .flush()
-        .map_err(|err| Error::new("flushing socket", &err))?;
-    Ok(())
+    server::write_message(socket, request)
 }
 
-pub fn show_stream() -> Result<()> {
+pub fn show_stream(socket_path: &Path, options: server::ShowOptions) -> Result<()> {
     let stdin = io::stdin();
-    let mut socket = UnixStream::connect(server::SOCKET_PATH)
-        .map_err(|err| Error::new("connecting to server", &err))?;
+    let (mut socket, mut buffer) = connect(socket_path, "show")?;
+    let mut responses = socket
+        .try_clone()
+        .map_err(|err| Error::new("cloning socket", &err))?;
+    let response_reader = thread::spawn(move || loop {
+        match server::read_message(&mut buffer, &mut responses) {
+            Ok(Some(server::Response::Error(err))) => eprintln!("{}", err),
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("Failed to receive response {:?}", err);
+                break;
+            }
+        }
+    });
     for line in stdin.lock().lines() {
         let line = line.map_err(|err| Error::new("reading io", &err))?;
         let tokens = line.split(' ').collect::<Vec<&str>>();
@@ -44,41 +150,187 @@ pub fn show_stream() -> Result<()> {
                 continue;
             }
         };
-        let value = match value_str.parse::<u8>() {
-            Ok(value) => value,
-            Err(_) => {
-                eprintln!("Expected `value` to be a u8");
+        // Streams are usually the output of another program, so values are always absolute.
+        let request = match parse_show_request(profile, Some(value_str), false, options.clone()) {
+            Ok(request) => request,
+            Err(err) => {
+                eprintln!("{}", err);
                 continue;
             }
         };
-        if value > 100 {
-            eprintln!("Expected `value` to be in [0, 100]");
-            continue;
-        }
-        if let Err(err) = send_request(&server::Request::Show { profile, value }, &mut socket) {
+        if let Err(err) = send_request(&request, &mut socket) {
             eprintln!("Failed to send request {:?}", err);
         }
     }
     socket
         .shutdown(Shutdown::Write)
         .map_err(|err| Error::new("shutting down connection", &err))?;
+    let _ = response_reader.join();
     Ok(())
 }
 
-pub fn show(profile: String, value: u8) -> Result<()> {
-    if value > 100 {
+/// Parses `value` as either an absolute value, or if `allow_relative` is set, a signed delta
+/// relative to the last value shown in the profile. A value prefixed with `=` is always absolute so
+/// that negative values can be shown. If there is no value, the last value is shown again.
+fn parse_show_request(
+    profile: String,
+    value: Option<&str>,
+    allow_relative: bool,
+    options: server::ShowOptions,
+) -> Result<server::Request> {
+    let value = match value {
+        Some(value) => value,
+        None => {
+            return Ok(server::Request::Adjust {
+                profile,
+                delta: 0.0,
+                options,
+            })
+        }
+    };
+
+    let is_relative = allow_relative && (value.starts_with('+') || value.starts_with('-'));
+    let number = value
+        .strip_prefix('=')
+        .unwrap_or(value)
+        .parse::<f64>()
+        .map_err(|err| Error::new("parsing `value`", &err))?;
+    if !number.is_finite() {
         return Err(Error::from_description(
-            "processing request",
-            "Expected `value` in [0, 100].",
+            "parsing `value`",
+            "Expected `value` to be a finite number.",
         ));
     }
-    send_one_request(&server::Request::Show { profile, value })
+
+    if is_relative {
+        return Ok(server::Request::Adjust {
+            profile,
+            delta: number,
+            options,
+        });
+    }
+
+    // The range of the value depends on the color profile, so it is validated by the daemon.
+    Ok(server::Request::Show {
+        profile,
+        value: number,
+        options,
+    })
+}
+
+pub fn show(
+    socket_path: &Path,
+    profile: String,
+    value: Option<&str>,
+    options: server::ShowOptions,
+    wait: bool,
+) -> Result<()> {
+    send_one_request(
+        socket_path,
+        &parse_show_request(profile, value, true, options)?,
+        wait,
+    )
+}
+
+pub fn toggle(socket_path: &Path, profile: Option<String>, wait: bool) -> Result<()> {
+    send_one_request(socket_path, &server::Request::Toggle { profile }, wait)
+}
+
+pub fn hide(socket_path: &Path, wait: bool) -> Result<()> {
+    send_one_request(socket_path, &server::Request::Hide, wait)
+}
+
+pub fn reload(socket_path: &Path, wait: bool) -> Result<()> {
+    send_one_request(socket_path, &server::Request::Reload, wait)
+}
+
+pub fn stop(socket_path: &Path, wait: bool) -> Result<()> {
+    send_one_request(socket_path, &server::Request::Stop, wait)
+}
+
+fn print_status(status: &server::Status) {
+    println!("pid: {}", status.pid);
+    println!("config: {}", status.config_path.display());
+    println!("visible: {}", status.visible);
+    if let Some(ref profile) = status.profile {
+        println!("profile: {}", profile);
+    }
+    if let Some(value) = status.value {
+        println!("value: {}", value);
+    }
+    println!("uptime: {}s", status.uptime_secs);
+    println!("last values:");
+    for (profile, value) in &status.last_values {
+        println!("    {}: {}", profile, value);
+    }
 }
 
-pub fn hide() -> Result<()> {
-    send_one_request(&server::Request::Hide)
+/// Prints the status of the daemon. Returns `false` if the daemon is not running.
+pub fn status(socket_path: &Path, json: bool) -> Result<bool> {
+    let socket = match UnixStream::connect(socket_path) {
+        Ok(socket) => socket,
+        Err(ref err)
+            if err.kind() == ErrorKind::NotFound || err.kind() == ErrorKind::ConnectionRefused =>
+        {
+            return Ok(false);
+        }
+        Err(err) => return Err(Error::new("connecting to server", &err)),
+    };
+    let (mut socket, mut buffer) = handshake(socket, "status")?;
+    send_request(&server::Request::Status, &mut socket)?;
+    let status = match receive_response(&mut socket, &mut buffer)? {
+        server::Response::Status(status) => status,
+        _ => {
+            return Err(Error::from_description(
+                "receiving response",
+                "Expected status from daemon.",
+            ))
+        }
+    };
+
+    if json {
+        let status =
+            serde_json::to_string(&status).map_err(|err| Error::new("serializing status", &err))?;
+        println!("{}", status);
+    } else {
+        print_status(&status);
+    }
+    Ok(true)
 }
 
-pub fn stop() -> Result<()> {
-    send_one_request(&server::Request::Stop)
+fn format_event(event: &server::Event, json: bool) -> String {
+    if json {
+        let event = match event {
+            server::Event::Show { profile, value } => {
+                json!({ "event": "show", "profile": profile, "value": value })
+            }
+            server::Event::Hide => json!({ "event": "hide" }),
+            server::Event::Timeout => json!({ "event": "timeout" }),
+            server::Event::Reload { error } => json!({
+                "event": "reload",
+                "error": error.as_ref().map(|err| err.to_string()),
+            }),
+        };
+        return event.to_string();
+    }
+
+    match event {
+        server::Event::Show { profile, value } => format!("show {} {}", profile, value),
+        server::Event::Hide => String::from("hide"),
+        server::Event::Timeout => String::from("timeout"),
+        server::Event::Reload { error: None } => String::from("reload"),
+        server::Event::Reload { error: Some(_) } => String::from("reload-failed"),
+    }
+}
+
+pub fn subscribe(socket_path: &Path, json: bool) -> Result<()> {
+    let (mut socket, mut buffer) = connect(socket_path, "subscribe")?;
+    send_request(&server::Request::Subscribe, &mut socket)?;
+    receive_response(&mut socket, &mut buffer)?;
+    let stdout = io::stdout();
+    while let Some(event) = server::read_message(&mut buffer, &mut socket)? {
+        writeln!(stdout.lock(), "{}", format_event(&event, json))
+            .map_err(|err| Error::new("writing event", &err))?;
+    }
+    Ok(())
 }
@@ -1,10 +1,12 @@
 use crate::Error;
 use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde_derive::Deserialize;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::env;
 use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str;
 
 #[derive(Copy, Clone, Deserialize)]
@@ -16,7 +18,59 @@ pub enum Direction {
     Right,
 }
 
-#[derive(Copy, Clone, Deserialize)]
+/// Where the fill of the bar starts from.
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillMode {
+    /// The bar fills from `min` in the fill direction.
+    #[default]
+    Edge,
+    /// The bar fills from 0 in the fill direction for positive values, and in the opposite
+    /// direction for negative values.
+    Center,
+}
+
+/// How a partially filled segment of a segmented bar is drawn.
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentFill {
+    /// The segment is filled proportionally.
+    Partial,
+    /// The segment is filled only if it is at least half filled.
+    #[default]
+    Round,
+    /// The segment is filled only if it is completely filled.
+    Floor,
+    /// The segment is filled if any of it is filled.
+    Ceil,
+}
+
+/// Where a label or text is drawn relative to the bar.
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelPosition {
+    /// The label is drawn in the middle of the bar.
+    Inside,
+    /// The label is drawn in the middle of the bar, which is drawn without its fill.
+    Replace,
+    /// The label is drawn to the left of a horizontal bar, or above a vertical bar.
+    Before,
+    /// The label is drawn to the right of a horizontal bar, or below a vertical bar.
+    #[default]
+    After,
+}
+
+/// Space reserved beside the bar for content such as a label. `before` and `after` are the sizes
+/// along the fill direction to the left and right of a horizontal bar, or above and below a
+/// vertical bar, and `thickness` is the size of the content across the fill direction.
+#[derive(Copy, Clone, Default)]
+pub struct Reserved {
+    pub before: u32,
+    pub after: u32,
+    pub thickness: u32,
+}
+
+#[derive(Clone, Deserialize)]
 pub struct GlobalConfig {
     #[serde(default)]
     pub x_center_relative: f32,
@@ -31,6 +85,14 @@ pub struct GlobalConfig {
     pub border: u32,
     #[serde(default)]
     pub padding: u32,
+    #[serde(default)]
+    pub window_radius: u32,
+    #[serde(default)]
+    pub border_radius: u32,
+    #[serde(default)]
+    pub fill_radius: u32,
+    #[serde(default = "GlobalConfig::default_opacity")]
+    pub opacity: f64,
 
     #[serde(default)]
     pub height_relative: f32,
@@ -42,7 +104,63 @@ pub struct GlobalConfig {
     #[serde(default = "GlobalConfig::default_timeout")]
     pub timeout: u64,
 
+    #[serde(default)]
+    pub min: f64,
+    #[serde(default = "GlobalConfig::default_max")]
+    pub max: f64,
+    #[serde(default)]
+    pub max_overflow: Option<f64>,
+
     pub fill_direction: Direction,
+    #[serde(default)]
+    pub fill_mode: FillMode,
+
+    #[serde(default)]
+    pub segments: u32,
+    #[serde(default)]
+    pub segment_gap: u32,
+    #[serde(default)]
+    pub segment_fill: SegmentFill,
+
+    #[serde(default)]
+    pub tick_interval: f64,
+    #[serde(default)]
+    pub ticks: Vec<f64>,
+    #[serde(default)]
+    pub markers: Vec<f64>,
+    #[serde(default)]
+    pub ghost: bool,
+    #[serde(default = "GlobalConfig::default_marker_size")]
+    pub marker_size: u32,
+
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default = "GlobalConfig::default_label_font")]
+    pub label_font: String,
+    #[serde(default)]
+    pub label_position: LabelPosition,
+
+    #[serde(default = "GlobalConfig::default_text_font")]
+    pub text_font: String,
+    #[serde(default = "GlobalConfig::default_text_size")]
+    pub text_size: f32,
+    #[serde(default)]
+    pub text_position: LabelPosition,
+
+    #[serde(default)]
+    pub icon: Option<PathBuf>,
+    #[serde(default)]
+    pub icon_glyph: Option<String>,
+    #[serde(default)]
+    pub alternate_icon: Option<PathBuf>,
+    #[serde(default)]
+    pub alternate_icon_glyph: Option<String>,
+    #[serde(default = "GlobalConfig::default_text_font")]
+    pub icon_font: String,
+    #[serde(default = "GlobalConfig::default_icon_size")]
+    pub icon_size: f32,
+    #[serde(default = "GlobalConfig::default_icon_position")]
+    pub icon_position: LabelPosition,
 }
 
 impl GlobalConfig {
@@ -50,14 +168,140 @@ impl GlobalConfig {
         1000
     }
 
-    pub fn total_width(&self, screen_width: u32) -> u32 {
+    fn default_max() -> f64 {
+        100.0
+    }
+
+    fn default_opacity() -> f64 {
+        1.0
+    }
+
+    fn default_marker_size() -> u32 {
+        1
+    }
+
+    fn default_label_font() -> String {
+        String::from("fixed")
+    }
+
+    fn default_text_font() -> String {
+        String::from("sans")
+    }
+
+    fn default_text_size() -> f32 {
+        14.0
+    }
+
+    fn default_icon_size() -> f32 {
+        16.0
+    }
+
+    fn default_icon_position() -> LabelPosition {
+        LabelPosition::Before
+    }
+
+    /// Returns the largest value that can be shown, which is `max_overflow` if it is set.
+    pub fn max_value(&self) -> f64 {
+        self.max_overflow.unwrap_or(self.max)
+    }
+
+    /// Returns the value that the fill of the bar starts from.
+    pub fn origin(&self) -> f64 {
+        match self.fill_mode {
+            FillMode::Edge => self.min,
+            FillMode::Center => 0.0_f64.clamp(self.min, self.max),
+        }
+    }
+
+    /// Returns the values to draw tick marks at, which are every `tick_interval` between `min` and
+    /// `max`, followed by `ticks`. At most `max_interval_ticks` are drawn at intervals, since ticks
+    /// closer than a pixel apart cannot be told apart.
+    pub fn tick_values(&self, max_interval_ticks: usize) -> Vec<f64> {
+        let mut values = Vec::new();
+        if self.tick_interval > 0.0 {
+            values.extend(
+                (1..)
+                    .map(|index| self.min + f64::from(index) * self.tick_interval)
+                    .take_while(|value| *value < self.max)
+                    .take(max_interval_ticks),
+            );
+        }
+        values.extend(&self.ticks);
+        values
+    }
+
+    /// Returns the fraction of the bar filled by `value`, which is greater than 1 if `value` is
+    /// above `max`.
+    pub fn fill_ratio(&self, value: f64) -> f64 {
+        (value - self.min) / (self.max - self.min)
+    }
+
+    pub fn is_horizontal(&self) -> bool {
+        match self.fill_direction {
+            Direction::Left | Direction::Right => true,
+            Direction::Up | Direction::Down => false,
+        }
+    }
+
+    /// Returns the label for `value`, replacing `{value}` with the value and `{percent}` with the
+    /// percentage of the bar that is filled.
+    pub fn format_label(&self, value: f64) -> Option<String> {
+        self.label.as_ref().map(|label| {
+            let percent = (self.fill_ratio(value) * 100.0).round();
+            label
+                .replace("{value}", &((value * 100.0).round() / 100.0).to_string())
+                .replace("{percent}", &percent.to_string())
+        })
+    }
+
+    /// Returns the width of the bar including its margins, border, and padding.
+    pub fn box_width(&self, screen_width: u32) -> u32 {
         2 * (self.border + self.margin + self.padding) + self.width(screen_width)
     }
 
-    pub fn total_height(&self, screen_height: u32) -> u32 {
+    /// Returns the height of the bar including its margins, border, and padding.
+    pub fn box_height(&self, screen_height: u32) -> u32 {
         2 * (self.border + self.margin + self.padding) + self.height(screen_height)
     }
 
+    /// Returns the offset of the bar from the top left corner of the window, which is after the
+    /// space reserved before the bar and centered across the fill direction.
+    pub fn box_offset(
+        &self,
+        screen_width: u32,
+        screen_height: u32,
+        reserved: Reserved,
+    ) -> (u32, u32) {
+        let width = self.total_width(screen_width, reserved);
+        let height = self.total_height(screen_height, reserved);
+        if self.is_horizontal() {
+            (
+                reserved.before,
+                (height - self.box_height(screen_height)) / 2,
+            )
+        } else {
+            ((width - self.box_width(screen_width)) / 2, reserved.before)
+        }
+    }
+
+    pub fn total_width(&self, screen_width: u32, reserved: Reserved) -> u32 {
+        let width = self.box_width(screen_width);
+        if self.is_horizontal() {
+            width + reserved.before + reserved.after
+        } else {
+            width.max(reserved.thickness + 2 * self.margin)
+        }
+    }
+
+    pub fn total_height(&self, screen_height: u32, reserved: Reserved) -> u32 {
+        let height = self.box_height(screen_height);
+        if self.is_horizontal() {
+            height.max(reserved.thickness + 2 * self.margin)
+        } else {
+            height + reserved.before + reserved.after
+        }
+    }
+
     pub fn width(&self, screen_width: u32) -> u32 {
         self.width_absolute + f32::round(screen_width as f32 * self.width_relative) as u32
     }
@@ -66,12 +310,22 @@ impl GlobalConfig {
         self.height_absolute + f32::round(screen_height as f32 * self.height_relative) as u32
     }
 
-    pub fn x(&self, screen_width: u32) -> u32 {
-        self.x_center(screen_width) - self.total_width(screen_width) / 2
+    /// Returns the x coordinate of the window, which is moved onto the screen if the window would
+    /// extend past its edges, such as when a long message is drawn beside the bar.
+    pub fn x(&self, screen_width: u32, reserved: Reserved) -> u32 {
+        let width = self.total_width(screen_width, reserved);
+        self.x_center(screen_width)
+            .saturating_sub(width / 2)
+            .min(screen_width.saturating_sub(width))
     }
 
-    pub fn y(&self, screen_height: u32) -> u32 {
-        self.y_center(screen_height) - self.total_height(screen_height) / 2
+    /// Returns the y coordinate of the window, which is moved onto the screen if the window would
+    /// extend past its edges.
+    pub fn y(&self, screen_height: u32, reserved: Reserved) -> u32 {
+        let height = self.total_height(screen_height, reserved);
+        self.y_center(screen_height)
+            .saturating_sub(height / 2)
+            .min(screen_height.saturating_sub(height))
     }
 
     pub fn x_center(&self, screen_width: u32) -> u32 {
@@ -83,11 +337,56 @@ impl GlobalConfig {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GradientMode {
+    /// The whole foreground is the color of the gradient at the value of the bar.
+    #[default]
+    Value,
+    /// The foreground changes color along the fill direction.
+    Bar,
+}
+
+#[derive(Clone)]
 pub struct ColorConfig {
     pub foreground: u32,
     pub background: u32,
     pub border: u32,
+    pub overflow: u32,
+    pub center: u32,
+    pub tick: u32,
+    pub marker: u32,
+    pub ghost: u32,
+    pub label: u32,
+    pub text: u32,
+    pub icon: u32,
+    pub thresholds: Vec<Threshold>,
+    pub gradient: Vec<GradientStop>,
+    pub gradient_mode: GradientMode,
+}
+
+/// Colors that override the colors of a profile when the value is below `below`.
+#[derive(Copy, Clone)]
+pub struct Threshold {
+    pub below: f64,
+    pub foreground: Option<u32>,
+    pub background: Option<u32>,
+    pub border: Option<u32>,
+}
+
+/// A foreground color at a specific value, which is interpolated with the neighbouring stops.
+#[derive(Copy, Clone)]
+pub struct GradientStop {
+    pub at: f64,
+    pub foreground: u32,
+}
+
+pub fn interpolate_color(from: u32, to: u32, ratio: f64) -> u32 {
+    [0, 8, 16, 24].iter().fold(0, |color, shift| {
+        let from = f64::from((from >> shift) & 0xff);
+        let to = f64::from((to >> shift) & 0xff);
+        color | (f64::round(from + (to - from) * ratio) as u32) << shift
+    })
 }
 
 impl ColorConfig {
@@ -96,7 +395,183 @@ impl ColorConfig {
             foreground,
             background,
             border,
+            overflow: border,
+            center: border,
+            tick: background,
+            marker: border,
+            ghost: foreground,
+            label: foreground,
+            text: foreground,
+            icon: foreground,
+            thresholds: Vec::new(),
+            gradient: Vec::new(),
+            gradient_mode: GradientMode::default(),
+        }
+    }
+
+    /// Returns the colors to use for `value`. The colors of the first threshold that `value` is
+    /// below take precedence over the gradient, which takes precedence over the colors of the
+    /// profile. The gradient is only kept if it is drawn along the bar.
+    pub fn for_value(&self, value: f64) -> ColorConfig {
+        let mut color_config = ColorConfig {
+            thresholds: Vec::new(),
+            gradient: Vec::new(),
+            gradient_mode: GradientMode::default(),
+            ..self.clone()
+        };
+        let threshold = self
+            .thresholds
+            .iter()
+            .find(|threshold| value < threshold.below);
+        if let Some(threshold) = threshold {
+            color_config.background = threshold.background.unwrap_or(self.background);
+            color_config.border = threshold.border.unwrap_or(self.border);
+            if let Some(foreground) = threshold.foreground {
+                color_config.foreground = foreground;
+                return color_config;
+            }
+        }
+
+        match self.gradient_mode {
+            GradientMode::Value => color_config.foreground = self.gradient_color(value),
+            GradientMode::Bar => {
+                color_config.gradient = self.gradient.clone();
+                color_config.gradient_mode = GradientMode::Bar;
+            }
+        }
+        color_config
+    }
+
+    /// Returns the colors with `f` applied to each color.
+    pub fn map_colors<F>(&self, f: F) -> ColorConfig
+    where
+        F: Fn(u32) -> u32,
+    {
+        // Every field is listed so that a new color cannot be left out.
+        ColorConfig {
+            foreground: f(self.foreground),
+            background: f(self.background),
+            border: f(self.border),
+            overflow: f(self.overflow),
+            center: f(self.center),
+            tick: f(self.tick),
+            marker: f(self.marker),
+            ghost: f(self.ghost),
+            label: f(self.label),
+            text: f(self.text),
+            icon: f(self.icon),
+            thresholds: self
+                .thresholds
+                .iter()
+                .map(|threshold| Threshold {
+                    below: threshold.below,
+                    foreground: threshold.foreground.map(&f),
+                    background: threshold.background.map(&f),
+                    border: threshold.border.map(&f),
+                })
+                .collect(),
+            gradient: self
+                .gradient
+                .iter()
+                .map(|stop| GradientStop {
+                    at: stop.at,
+                    foreground: f(stop.foreground),
+                })
+                .collect(),
+            gradient_mode: self.gradient_mode,
+        }
+    }
+
+    /// Returns the color of the gradient at `position`, or the foreground if there is no
+    /// gradient.
+    pub fn gradient_color(&self, position: f64) -> u32 {
+        let next_index = self.gradient.iter().position(|stop| position <= stop.at);
+        match next_index {
+            _ if self.gradient.is_empty() => self.foreground,
+            Some(0) => self.gradient[0].foreground,
+            Some(next_index) => {
+                let prev = self.gradient[next_index - 1];
+                let next = self.gradient[next_index];
+                let ratio = (position - prev.at) / (next.at - prev.at);
+                interpolate_color(prev.foreground, next.foreground, ratio)
+            }
+            None => self.gradient[self.gradient.len() - 1].foreground,
+        }
+    }
+}
+
+fn parse_color<E>(color: &str) -> Result<u32, E>
+where
+    E: de::Error,
+{
+    let invalid_color = || {
+        de::Error::invalid_value(
+            de::Unexpected::Str(color),
+            &"a hex color in the format `#RRGGBB` or `#RRGGBBAA`",
+        )
+    };
+    let digits = match color.strip_prefix('#') {
+        Some(digits) if digits.chars().all(|c| c.is_ascii_hexdigit()) => digits,
+        _ => return Err(invalid_color()),
+    };
+    let value = u32::from_str_radix(digits, 16).map_err(|_| invalid_color())?;
+
+    // Colors are stored as `0xAARRGGBB`, and colors without an alpha channel are opaque.
+    match digits.len() {
+        6 => Ok(0xff00_0000 | value),
+        8 => Ok(value.rotate_right(8)),
+        _ => Err(invalid_color()),
+    }
+}
+
+impl<'de> Deserialize<'de> for Threshold {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawThreshold {
+            below: f64,
+            foreground: Option<String>,
+            background: Option<String>,
+            border: Option<String>,
         }
+
+        let raw_threshold = RawThreshold::deserialize(deserializer)?;
+        Ok(Threshold {
+            below: raw_threshold.below,
+            foreground: raw_threshold
+                .foreground
+                .map(|color| parse_color(&color))
+                .transpose()?,
+            background: raw_threshold
+                .background
+                .map(|color| parse_color(&color))
+                .transpose()?,
+            border: raw_threshold
+                .border
+                .map(|color| parse_color(&color))
+                .transpose()?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for GradientStop {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawGradientStop {
+            at: f64,
+            foreground: String,
+        }
+
+        let raw_gradient_stop = RawGradientStop::deserialize(deserializer)?;
+        Ok(GradientStop {
+            at: raw_gradient_stop.at,
+            foreground: parse_color(&raw_gradient_stop.foreground)?,
+        })
     }
 }
 
@@ -111,6 +586,18 @@ impl<'de> Deserialize<'de> for ColorConfig {
             Foreground,
             Background,
             Border,
+            Overflow,
+            Center,
+            Tick,
+            Marker,
+            Ghost,
+            Label,
+            Text,
+            Icon,
+            Thresholds,
+            Gradient,
+            #[serde(rename = "gradient_mode")]
+            GradientMode,
         }
 
         struct ColorConfigVisitor;
@@ -129,21 +616,15 @@ impl<'de> Deserialize<'de> for ColorConfig {
                 let foreground: String = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(0, &self))?;
-                let foreground = u32::from_str_radix(&foreground[1..], 16).map_err(|_| {
-                    de::Error::invalid_value(de::Unexpected::Str(&foreground), &"a hex color")
-                })?;
+                let foreground = parse_color(&foreground)?;
                 let background: String = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let background = u32::from_str_radix(&background[1..], 16).map_err(|_| {
-                    de::Error::invalid_value(de::Unexpected::Str(&background), &"a hex color")
-                })?;
+                let background = parse_color(&background)?;
                 let border: String = seq
                     .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let border = u32::from_str_radix(&border[1..], 16).map_err(|_| {
-                    de::Error::invalid_value(de::Unexpected::Str(&border), &"a hex color")
-                })?;
+                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                let border = parse_color(&border)?;
                 Ok(ColorConfig::new(foreground, background, border))
             }
 
@@ -154,6 +635,17 @@ impl<'de> Deserialize<'de> for ColorConfig {
                 let mut foreground: Option<String> = None;
                 let mut background: Option<String> = None;
                 let mut border: Option<String> = None;
+                let mut overflow: Option<String> = None;
+                let mut center: Option<String> = None;
+                let mut tick: Option<String> = None;
+                let mut marker: Option<String> = None;
+                let mut ghost: Option<String> = None;
+                let mut label: Option<String> = None;
+                let mut text: Option<String> = None;
+                let mut icon: Option<String> = None;
+                let mut thresholds: Option<Vec<Threshold>> = None;
+                let mut gradient: Option<Vec<GradientStop>> = None;
+                let mut gradient_mode: Option<GradientMode> = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Foreground => {
@@ -174,36 +666,287 @@ impl<'de> Deserialize<'de> for ColorConfig {
                             }
                             border = Some(map.next_value()?);
                         }
+                        Field::Overflow => {
+                            if overflow.is_some() {
+                                return Err(de::Error::duplicate_field("overflow"));
+                            }
+                            overflow = Some(map.next_value()?);
+                        }
+                        Field::Center => {
+                            if center.is_some() {
+                                return Err(de::Error::duplicate_field("center"));
+                            }
+                            center = Some(map.next_value()?);
+                        }
+                        Field::Tick => {
+                            if tick.is_some() {
+                                return Err(de::Error::duplicate_field("tick"));
+                            }
+                            tick = Some(map.next_value()?);
+                        }
+                        Field::Marker => {
+                            if marker.is_some() {
+                                return Err(de::Error::duplicate_field("marker"));
+                            }
+                            marker = Some(map.next_value()?);
+                        }
+                        Field::Ghost => {
+                            if ghost.is_some() {
+                                return Err(de::Error::duplicate_field("ghost"));
+                            }
+                            ghost = Some(map.next_value()?);
+                        }
+                        Field::Label => {
+                            if label.is_some() {
+                                return Err(de::Error::duplicate_field("label"));
+                            }
+                            label = Some(map.next_value()?);
+                        }
+                        Field::Text => {
+                            if text.is_some() {
+                                return Err(de::Error::duplicate_field("text"));
+                            }
+                            text = Some(map.next_value()?);
+                        }
+                        Field::Icon => {
+                            if icon.is_some() {
+                                return Err(de::Error::duplicate_field("icon"));
+                            }
+                            icon = Some(map.next_value()?);
+                        }
+                        Field::Thresholds => {
+                            if thresholds.is_some() {
+                                return Err(de::Error::duplicate_field("thresholds"));
+                            }
+                            thresholds = Some(map.next_value()?);
+                        }
+                        Field::Gradient => {
+                            if gradient.is_some() {
+                                return Err(de::Error::duplicate_field("gradient"));
+                            }
+                            gradient = Some(map.next_value()?);
+                        }
+                        Field::GradientMode => {
+                            if gradient_mode.is_some() {
+                                return Err(de::Error::duplicate_field("gradient_mode"));
+                            }
+                            gradient_mode = Some(map.next_value()?);
+                        }
                     }
                 }
 
                 let foreground =
                     foreground.ok_or_else(|| de::Error::missing_field("foreground"))?;
-                let foreground = u32::from_str_radix(&foreground[1..], 16).map_err(|_| {
-                    de::Error::invalid_value(de::Unexpected::Str(&foreground), &"a hex color")
-                })?;
+                let foreground = parse_color(&foreground)?;
                 let background =
                     background.ok_or_else(|| de::Error::missing_field("background"))?;
-                let background = u32::from_str_radix(&background[1..], 16).map_err(|_| {
-                    de::Error::invalid_value(de::Unexpected::Str(&background), &"a hex color")
-                })?;
+                let background = parse_color(&background)?;
                 let border = border.ok_or_else(|| de::Error::missing_field("border"))?;
-                let border = u32::from_str_radix(&border[1..], 16).map_err(|_| {
-                    de::Error::invalid_value(de::Unexpected::Str(&border), &"a hex color")
-                })?;
+                let border = parse_color(&border)?;
 
-                Ok(ColorConfig::new(foreground, background, border))
+                let mut color_config = ColorConfig::new(foreground, background, border);
+                if let Some(overflow) = overflow {
+                    color_config.overflow = parse_color(&overflow)?;
+                }
+                if let Some(center) = center {
+                    color_config.center = parse_color(&center)?;
+                }
+                if let Some(tick) = tick {
+                    color_config.tick = parse_color(&tick)?;
+                }
+                if let Some(marker) = marker {
+                    color_config.marker = parse_color(&marker)?;
+                }
+                if let Some(ghost) = ghost {
+                    color_config.ghost = parse_color(&ghost)?;
+                }
+                if let Some(label) = label {
+                    color_config.label = parse_color(&label)?;
+                }
+                if let Some(text) = text {
+                    color_config.text = parse_color(&text)?;
+                }
+                if let Some(icon) = icon {
+                    color_config.icon = parse_color(&icon)?;
+                }
+                color_config.thresholds = thresholds.unwrap_or_default();
+                color_config
+                    .thresholds
+                    .sort_by(|a, b| a.below.partial_cmp(&b.below).unwrap_or(Ordering::Equal));
+                color_config.gradient = gradient.unwrap_or_default();
+                color_config
+                    .gradient
+                    .sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap_or(Ordering::Equal));
+                color_config.gradient_mode = gradient_mode.unwrap_or_default();
+                Ok(color_config)
             }
         }
 
-        const FIELDS: &[&str] = &["secs", "nanos"];
+        const FIELDS: &[&str] = &[
+            "foreground",
+            "background",
+            "border",
+            "overflow",
+            "center",
+            "tick",
+            "marker",
+            "ghost",
+            "label",
+            "text",
+            "icon",
+            "thresholds",
+            "gradient",
+            "gradient_mode",
+        ];
         deserializer.deserialize_struct("ColorConfig", FIELDS, ColorConfigVisitor)
     }
 }
 
-pub fn parse_config<P>(
-    config_path: P,
-) -> super::Result<(GlobalConfig, HashMap<String, ColorConfig>)>
+/// The settings of a profile, which are the global settings with the profile's overrides applied,
+/// and its colors.
+#[derive(Clone)]
+pub struct ProfileConfig {
+    pub global_config: GlobalConfig,
+    pub color_config: ColorConfig,
+}
+
+/// Returns the XDG base directory in `var` if it is set to an absolute path.
+pub fn xdg_dir(var: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+/// Returns the first configuration file that exists in the XDG base directories, searching
+/// `$XDG_CONFIG_HOME` (or `$HOME/.config`) followed by each entry of `$XDG_CONFIG_DIRS` (or
+/// `/etc/xdg`).
+pub fn find_config_path() -> super::Result<PathBuf> {
+    let config_home = xdg_dir("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    let config_dirs = match env::var_os("XDG_CONFIG_DIRS") {
+        Some(ref dirs) if !dirs.is_empty() => env::split_paths(dirs)
+            .filter(|dir| dir.is_absolute())
+            .collect(),
+        _ => vec![PathBuf::from("/etc/xdg")],
+    };
+
+    let candidates = config_home
+        .into_iter()
+        .chain(config_dirs)
+        .map(|dir| {
+            dir.join(env!("CARGO_PKG_NAME"))
+                .join(format!("{}.toml", env!("CARGO_PKG_NAME")))
+        })
+        .collect::<Vec<_>>();
+
+    match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(Error::from_description(
+            "finding config",
+            format!(
+                "No config found in any of {}.",
+                candidates
+                    .iter()
+                    .map(|path| format!("`{}`", path.display()))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        )),
+    }
+}
+
+fn into_table(value: toml::Value, section: &str) -> super::Result<toml::value::Table> {
+    match value {
+        toml::Value::Table(table) => Ok(table),
+        _ => Err(Error::from_description(
+            "parsing config",
+            format!("Expected table in `{}` section.", section),
+        )),
+    }
+}
+
+/// Returns the colors and settings of a profile with the colors and settings of the profiles it
+/// `extends` merged underneath. `chain` holds the profiles that are currently being resolved and
+/// is used to detect cycles.
+fn resolve_profile(
+    profile_name: &str,
+    color_values: &toml::value::Table,
+    profile_values: &toml::value::Table,
+    chain: &mut Vec<String>,
+) -> super::Result<(toml::Value, toml::value::Table)> {
+    if chain.iter().any(|name| name == profile_name) {
+        chain.push(profile_name.to_owned());
+        return Err(Error::from_description(
+            "parsing config",
+            format!(
+                "Expected no cycles in `extends`, but found {}.",
+                chain
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+            ),
+        ));
+    }
+
+    let mut color_value = match color_values.get(profile_name) {
+        Some(color_value) => color_value.clone(),
+        None => {
+            return Err(Error::from_description(
+                "parsing config",
+                format!(
+                    "Expected color profile `{}` extended by `{}` in `colors` section.",
+                    profile_name,
+                    chain.last().map(String::as_str).unwrap_or_default(),
+                ),
+            ))
+        }
+    };
+    let profile_table = match profile_values.get(profile_name) {
+        Some(profile_value) => {
+            into_table(profile_value.clone(), &format!("profiles.{}", profile_name))?
+        }
+        None => toml::value::Table::new(),
+    };
+
+    let parent_name = match color_value {
+        toml::Value::Table(ref mut color_table) => color_table.remove("extends"),
+        _ => None,
+    };
+    let parent_name = match parent_name {
+        Some(toml::Value::String(parent_name)) => parent_name,
+        Some(_) => {
+            return Err(Error::from_description(
+                format!("parsing color profile `{}`", profile_name),
+                "Expected `extends` to be the name of a color profile.",
+            ))
+        }
+        None => return Ok((color_value, profile_table)),
+    };
+
+    chain.push(profile_name.to_owned());
+    let (parent_color_value, mut parent_profile_table) =
+        resolve_profile(&parent_name, color_values, profile_values, chain)?;
+    chain.pop();
+
+    let color_value = match (parent_color_value, color_value) {
+        (toml::Value::Table(mut parent_color_table), toml::Value::Table(color_table)) => {
+            parent_color_table.extend(color_table);
+            toml::Value::Table(parent_color_table)
+        }
+        _ => {
+            return Err(Error::from_description(
+                format!("parsing color profile `{}`", profile_name),
+                format!("Expected color profile `{}` to be a table.", parent_name),
+            ))
+        }
+    };
+    parent_profile_table.extend(profile_table);
+
+    Ok((color_value, parent_profile_table))
+}
+
+pub fn parse_config<P>(config_path: P) -> super::Result<HashMap<String, ProfileConfig>>
 where
     P: AsRef<Path>,
 {
@@ -221,31 +964,440 @@ where
         )),
     }?;
 
-    let global_value = toml_table.remove("global").take().ok_or_else(|| {
+    let global_value = toml_table.remove("global").ok_or_else(|| {
         Error::from_description("parsing config", "Expected `global` section in config.")
     })?;
-    let global_config = global_value
+    let global_table = into_table(global_value, "global")?;
+    toml::Value::Table(global_table.clone())
         .try_into::<GlobalConfig>()
         .map_err(|err| Error::new("parsing config", &err))?;
 
-    let mut color_configs = HashMap::new();
-    let color_values = toml_table.remove("colors").take().ok_or_else(|| {
+    let color_values = toml_table.remove("colors").ok_or_else(|| {
         Error::from_description("parsing config", "Expected `colors` section in config.")
     })?;
-    let color_values = match color_values {
-        toml::Value::Table(table) => Ok(table),
-        _ => Err(Error::from_description(
+    let color_values = into_table(color_values, "colors")?;
+    let profile_values = match toml_table.remove("profiles") {
+        Some(profile_values) => into_table(profile_values, "profiles")?,
+        None => toml::value::Table::new(),
+    };
+
+    if let Some(profile_name) = profile_values
+        .keys()
+        .find(|profile_name| !color_values.contains_key(*profile_name))
+    {
+        return Err(Error::from_description(
             "parsing config",
-            "Expected table in `colors` section.",
-        )),
-    }?;
+            format!(
+                "Expected color profile `{}` in `colors` section for `profiles.{}`.",
+                profile_name, profile_name,
+            ),
+        ));
+    }
+
+    let mut profile_configs = HashMap::new();
+    for profile_name in color_values.keys() {
+        let (color_value, profile_table) = resolve_profile(
+            profile_name,
+            &color_values,
+            &profile_values,
+            &mut Vec::new(),
+        )?;
+        let color_config = color_value
+            .try_into::<ColorConfig>()
+            .map_err(|err| Error::new(format!("parsing color profile `{}`", profile_name), &err))?;
+
+        // Settings of the profile are merged over the global settings before being parsed.
+        let mut merged_table = global_table.clone();
+        merged_table.extend(profile_table);
+        let mut global_config = toml::Value::Table(merged_table)
+            .try_into::<GlobalConfig>()
+            .map_err(|err| Error::new(format!("parsing profile `{}`", profile_name), &err))?;
+        if !global_config.min.is_finite()
+            || !global_config.max.is_finite()
+            || !global_config.max_value().is_finite()
+        {
+            return Err(Error::from_description(
+                format!("parsing profile `{}`", profile_name),
+                "Expected `min`, `max`, and `max_overflow` to be finite numbers.",
+            ));
+        }
+        if global_config.max.partial_cmp(&global_config.min) != Some(Ordering::Greater) {
+            return Err(Error::from_description(
+                format!("parsing profile `{}`", profile_name),
+                "Expected `max` to be greater than `min`.",
+            ));
+        }
+        if !global_config.tick_interval.is_finite() || global_config.tick_interval < 0.0 {
+            return Err(Error::from_description(
+                format!("parsing profile `{}`", profile_name),
+                "Expected `tick_interval` to be a finite number that is at least 0.",
+            ));
+        }
+        if global_config.max_value() < global_config.max {
+            return Err(Error::from_description(
+                format!("parsing profile `{}`", profile_name),
+                "Expected `max_overflow` to be at least `max`.",
+            ));
+        }
+        if !(0.0..=1.0).contains(&global_config.opacity) {
+            return Err(Error::from_description(
+                format!("parsing profile `{}`", profile_name),
+                "Expected `opacity` in [0, 1].",
+            ));
+        }
+
+        // Icons are relative to the directory of the config file.
+        if let Some(config_dir) = config_path.as_ref().parent() {
+            global_config.icon = global_config.icon.map(|icon| config_dir.join(icon));
+            global_config.alternate_icon = global_config
+                .alternate_icon
+                .map(|alternate_icon| config_dir.join(alternate_icon));
+        }
+
+        profile_configs.insert(
+            profile_name.clone(),
+            ProfileConfig {
+                global_config,
+                color_config,
+            },
+        );
+    }
+
+    Ok(profile_configs)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    /// Parses a config with the `global` settings required by every config followed by `extra`.
+    pub(crate) fn parse(extra: &str) -> super::super::Result<HashMap<String, ProfileConfig>> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let config_path = env::temp_dir().join(format!(
+            "robar-config-test-{}-{}.toml",
+            process::id(),
+            COUNT.fetch_add(1, AtomicOrdering::SeqCst),
+        ));
+        let config = format!(
+            r##"
+                [global]
+                x_center_absolute = 0
+                y_center_absolute = 0
+                height_absolute = 10
+                width_absolute = 100
+                fill_direction = "right"
+                {}
+
+                [colors.volume]
+                foreground = "#ffffff"
+                background = "#000000"
+                border = "#ffffff"
+            "##,
+            extra,
+        );
+        fs::write(&config_path, config).unwrap();
+        let profile_configs = parse_config(&config_path);
+        fs::remove_file(&config_path).unwrap();
+        profile_configs
+    }
+
+    fn parse_error(extra: &str) -> String {
+        match parse(extra) {
+            Ok(_) => panic!("Expected config to be invalid."),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn inherits_profiles() {
+        let profile_configs = parse(
+            r##"
+                [colors.base]
+                foreground = "#111111"
+                background = "#222222"
+                border = "#333333"
 
-    for (profile_name, color_value) in color_values {
-        let color_config = color_value.try_into::<ColorConfig>().map_err(|err| {
-            Error::new(format!("parsing color profile `{}`", &profile_name), &err)
-        })?;
-        color_configs.insert(profile_name, color_config);
+                [colors.middle]
+                extends = "base"
+                background = "#444444"
+
+                [colors.child]
+                extends = "middle"
+                border = "#555555"
+
+                [profiles.base]
+                timeout = 100
+                max = 200
+
+                [profiles.middle]
+                timeout = 50
+            "##,
+        )
+        .unwrap();
+        let child = &profile_configs["child"];
+        assert_eq!(child.color_config.foreground, 0xff11_1111);
+        assert_eq!(child.color_config.background, 0xff44_4444);
+        assert_eq!(child.color_config.border, 0xff55_5555);
+        assert_eq!(child.global_config.timeout, 50);
+        assert_eq!(child.global_config.max, 200.0);
+        assert_eq!(profile_configs["base"].global_config.timeout, 100);
+        assert_eq!(profile_configs["middle"].color_config.border, 0xff33_3333);
+    }
+
+    #[test]
+    fn rejects_cycles_in_extends() {
+        let err = parse_error(
+            r##"
+                [colors.a]
+                extends = "b"
+
+                [colors.b]
+                extends = "a"
+            "##,
+        );
+        assert!(
+            err.contains("Expected no cycles in `extends`, but found `a` -> `b` -> `a`."),
+            "{}",
+            err
+        );
+
+        let err = parse_error(
+            r##"
+                [colors.a]
+                extends = "a"
+            "##,
+        );
+        assert!(
+            err.contains("Expected no cycles in `extends`, but found `a` -> `a`."),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_extends() {
+        let err = parse_error(
+            r##"
+                [colors.a]
+                extends = "missing"
+            "##,
+        );
+        assert!(
+            err.contains("Expected color profile `missing` extended by `a` in `colors` section."),
+            "{}",
+            err
+        );
+
+        let err = parse_error(
+            r##"
+                [colors.a]
+                extends = 1
+            "##,
+        );
+        assert!(
+            err.contains("Expected `extends` to be the name of a color profile."),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn selects_first_threshold_below_value() {
+        let profile_configs = parse(
+            r##"
+                [colors.battery]
+                foreground = "#000001"
+                background = "#000002"
+                border = "#000003"
+                thresholds = [
+                  { below = 30, foreground = "#000030", border = "#000033" },
+                  { below = 15, foreground = "#000015" },
+                ]
+            "##,
+        )
+        .unwrap();
+        let color_config = &profile_configs["battery"].color_config;
+        let colors = |value| {
+            let color_config = color_config.for_value(value);
+            (
+                color_config.foreground,
+                color_config.background,
+                color_config.border,
+            )
+        };
+        assert_eq!(colors(10.0), (0xff00_0015, 0xff00_0002, 0xff00_0003));
+        assert_eq!(colors(20.0), (0xff00_0030, 0xff00_0002, 0xff00_0033));
+        assert_eq!(colors(30.0), (0xff00_0001, 0xff00_0002, 0xff00_0003));
+    }
+
+    #[test]
+    fn prefers_threshold_foreground_over_gradient() {
+        let profile_configs = parse(
+            r##"
+                [colors.battery]
+                foreground = "#000001"
+                background = "#000002"
+                border = "#000003"
+                thresholds = [
+                  { below = 15, foreground = "#000015" },
+                  { below = 30, background = "#000030" },
+                ]
+                gradient = [
+                  { at = 0, foreground = "#000000" },
+                  { at = 100, foreground = "#0000c8" },
+                ]
+            "##,
+        )
+        .unwrap();
+        let color_config = &profile_configs["battery"].color_config;
+        let for_value = color_config.for_value(10.0);
+        assert_eq!(for_value.foreground, 0xff00_0015);
+        let for_value = color_config.for_value(20.0);
+        assert_eq!(for_value.foreground, 0xff00_0028);
+        assert_eq!(for_value.background, 0xff00_0030);
+        assert!(for_value.gradient.is_empty());
+        assert!(for_value.thresholds.is_empty());
+    }
+
+    #[test]
+    fn interpolates_colors() {
+        assert_eq!(
+            interpolate_color(0x0000_0000, 0xffff_8040, 0.0),
+            0x0000_0000
+        );
+        assert_eq!(
+            interpolate_color(0x0000_0000, 0xffff_8040, 0.5),
+            0x8080_4020
+        );
+        assert_eq!(
+            interpolate_color(0x0000_0000, 0xffff_8040, 1.0),
+            0xffff_8040
+        );
+        assert_eq!(
+            interpolate_color(0xff00_00c8, 0xff00_0000, 0.25),
+            0xff00_0096
+        );
     }
 
-    Ok((global_config, color_configs))
+    #[test]
+    fn selects_gradient_colors() {
+        let profile_configs = parse(
+            r##"
+                [colors.battery]
+                foreground = "#000001"
+                background = "#000002"
+                border = "#000003"
+                gradient = [
+                  { at = 80, foreground = "#0000c8" },
+                  { at = 20, foreground = "#000000" },
+                ]
+            "##,
+        )
+        .unwrap();
+        let color_config = &profile_configs["battery"].color_config;
+        assert_eq!(color_config.gradient_color(0.0), 0xff00_0000);
+        assert_eq!(color_config.gradient_color(20.0), 0xff00_0000);
+        assert_eq!(color_config.gradient_color(50.0), 0xff00_0064);
+        assert_eq!(color_config.gradient_color(80.0), 0xff00_00c8);
+        assert_eq!(color_config.gradient_color(100.0), 0xff00_00c8);
+
+        let profile_configs = parse("").unwrap();
+        let color_config = &profile_configs["volume"].color_config;
+        assert_eq!(color_config.gradient_color(50.0), color_config.foreground);
+    }
+
+    #[test]
+    fn selects_gradient_colors_at_equal_stops() {
+        let profile_configs = parse(
+            r##"
+                [colors.battery]
+                foreground = "#000001"
+                background = "#000002"
+                border = "#000003"
+                gradient = [
+                  { at = 0, foreground = "#000000" },
+                  { at = 50, foreground = "#0000ff" },
+                  { at = 50, foreground = "#00ff00" },
+                  { at = 100, foreground = "#000000" },
+                ]
+            "##,
+        )
+        .unwrap();
+        let color_config = &profile_configs["battery"].color_config;
+        assert_eq!(color_config.gradient_color(25.0), 0xff00_0080);
+        assert_eq!(color_config.gradient_color(50.0), 0xff00_00ff);
+        assert_eq!(color_config.gradient_color(75.0), 0xff00_8000);
+    }
+
+    #[test]
+    fn rejects_ranges_that_are_not_finite() {
+        assert!(parse("min = nan").is_err());
+        assert!(parse("max = nan").is_err());
+        assert!(parse("max = inf").is_err());
+        assert!(parse("min = -inf").is_err());
+        assert!(parse("max_overflow = nan").is_err());
+        assert!(parse("max_overflow = inf").is_err());
+        assert!(parse("min = 100\nmax = 0").is_err());
+        assert!(parse("min = -20\nmax = 40\nmax_overflow = 50").is_ok());
+    }
+
+    #[test]
+    fn keeps_window_on_screen() {
+        let profile_configs = parse("").unwrap();
+        let global_config = &profile_configs["volume"].global_config;
+        let reserved = Reserved {
+            before: 500,
+            after: 0,
+            thickness: 0,
+        };
+        assert_eq!(global_config.x(1920, Reserved::default()), 0);
+        assert_eq!(global_config.x(1920, reserved), 0);
+        assert_eq!(global_config.x(100, reserved), 0);
+
+        let profile_configs = parse("x_center_relative = 1.0").unwrap();
+        let global_config = &profile_configs["volume"].global_config;
+        assert_eq!(global_config.x(1920, reserved), 1920 - 600);
+    }
+
+    #[test]
+    fn parses_colors() {
+        let parse_color = |color| parse_color::<toml::de::Error>(color);
+        assert_eq!(parse_color("#112233").unwrap(), 0xff11_2233);
+        assert_eq!(parse_color("#11223344").unwrap(), 0x4411_2233);
+        assert_eq!(parse_color("#AbCdEf").unwrap(), 0xffab_cdef);
+        for color in &[
+            "#fff",
+            "#1234567",
+            "#123456789",
+            "112233",
+            "#+12345",
+            "#11223g",
+            "",
+        ] {
+            assert!(
+                parse_color(color).is_err(),
+                "Expected `{}` to be invalid.",
+                color
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_tick_intervals() {
+        assert!(parse("tick_interval = -10").is_err());
+        assert!(parse("tick_interval = nan").is_err());
+        assert!(parse("tick_interval = inf").is_err());
+        assert!(parse("tick_interval = 0").is_ok());
+    }
+
+    #[test]
+    fn limits_tick_count() {
+        let profile_configs = parse("tick_interval = 1e-12\nticks = [50]").unwrap();
+        let global_config = &profile_configs["volume"].global_config;
+        let tick_values = global_config.tick_values(100);
+        assert_eq!(tick_values.len(), 101);
+        assert_eq!(tick_values.last(), Some(&50.0));
+    }
 }
@@ -1,13 +1,33 @@
 use crate::config;
+use crate::icon::{self, Icon};
+use crate::server;
+use crate::text;
+use ab_glyph::FontVec;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use xcb::{self, randr};
 
+/// The tolerance for rounding errors when deciding whether a segment is completely filled.
+const SEGMENT_FILL_TOLERANCE: f64 = 1e-9;
+/// The maximum number of characters in a text item of a `PolyText8` request.
+const MAX_TEXT_ITEM_LENGTH: usize = 254;
+
 pub struct Display {
     connection: xcb::Connection,
     window: u32,
     gc: u32,
     screen_index: usize,
     screen_resources: Option<randr::GetScreenResourcesReply>,
-    previous_screen: Option<ScreenInfo>,
+    previous_geometry: Option<WindowGeometry>,
+    /// Whether the X Shape extension is available to round the corners of the window.
+    shape: bool,
+    /// Whether the window has a 32-bit visual with an alpha channel.
+    argb: bool,
+    /// The value of `_NET_WM_WINDOW_OPACITY` if the window does not have an alpha channel.
+    window_opacity: Option<u32>,
+    fonts: HashMap<String, Option<Font>>,
+    text_fonts: HashMap<String, Option<FontVec>>,
+    icons: HashMap<PathBuf, Option<Icon>>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -18,8 +38,46 @@ struct ScreenInfo {
     y: i16,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct WindowGeometry {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    radius: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Font {
+    id: u32,
+    ascent: u16,
+    descent: u16,
+}
+
+enum ContentKind {
+    /// A label encoded in Latin-1 for a core font.
+    Label { text: Vec<u8>, font: Font },
+    /// Text rendered from a fontconfig font.
+    Text(text::Bitmap),
+    /// An icon loaded from a file or rendered from a glyph of a fontconfig font.
+    Icon(Icon),
+}
+
+/// Something drawn inside or beside the bar. The reserved size is the space taken up beside the
+/// bar, which may be larger than the size of the content.
+struct Content {
+    kind: ContentKind,
+    position: config::LabelPosition,
+    width: u16,
+    height: u16,
+    reserved_width: u16,
+    reserved_height: u16,
+}
+
 impl Display {
-    fn init_window(&self) {
+    /// Creates the window with `argb_visual` if it is set, otherwise with the visual of the root
+    /// window.
+    fn init_window(&self, argb_visual: Option<u32>) {
         let screen = self
             .connection
             .get_setup()
@@ -27,31 +85,58 @@ impl Display {
             .nth(self.screen_index)
             .expect("Expected screen to exist.");
 
-        xcb::create_window(
-            &self.connection,
-            xcb::COPY_FROM_PARENT as u8,
-            self.window,
-            screen.root(),
-            0,
-            0,
-            1,
-            1,
-            0,
-            xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
-            screen.root_visual(),
-            &[(xcb::CW_OVERRIDE_REDIRECT, 1)],
-        );
+        match argb_visual {
+            Some(visual) => {
+                // A window with a different visual than its parent needs its own colormap.
+                let colormap = self.connection.generate_id();
+                xcb::create_colormap(
+                    &self.connection,
+                    xcb::COLORMAP_ALLOC_NONE as u8,
+                    colormap,
+                    screen.root(),
+                    visual,
+                );
+                xcb::create_window(
+                    &self.connection,
+                    32,
+                    self.window,
+                    screen.root(),
+                    0,
+                    0,
+                    1,
+                    1,
+                    0,
+                    xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
+                    visual,
+                    &[
+                        (xcb::CW_BACK_PIXEL, 0),
+                        (xcb::CW_BORDER_PIXEL, 0),
+                        (xcb::CW_OVERRIDE_REDIRECT, 1),
+                        (xcb::CW_COLORMAP, colormap),
+                    ],
+                );
+            }
+            None => {
+                xcb::create_window(
+                    &self.connection,
+                    xcb::COPY_FROM_PARENT as u8,
+                    self.window,
+                    screen.root(),
+                    0,
+                    0,
+                    1,
+                    1,
+                    0,
+                    xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
+                    screen.root_visual(),
+                    &[(xcb::CW_OVERRIDE_REDIRECT, 1)],
+                );
+            }
+        }
     }
 
     fn init_gc(&self) {
-        let screen = self
-            .connection
-            .get_setup()
-            .roots()
-            .nth(self.screen_index)
-            .expect("Expected screen to exist.");
-
-        xcb::create_gc(&self.connection, self.gc, screen.root(), &[]);
+        xcb::create_gc(&self.connection, self.gc, self.window, &[]);
     }
 
     pub fn new() -> Result<Self, xcb::ConnError> {
@@ -59,6 +144,10 @@ impl Display {
         let screen_index = screen_index as usize;
         let window = connection.generate_id();
         let gc = connection.generate_id();
+        let shape = connection
+            .get_extension_data(xcb::shape::id())
+            .is_some_and(|data| data.present());
+        let argb_visual = find_argb_visual(&connection, screen_index);
 
         let ret = Display {
             connection,
@@ -66,10 +155,16 @@ impl Display {
             gc,
             screen_index,
             screen_resources: None,
-            previous_screen: None,
+            previous_geometry: None,
+            shape,
+            argb: argb_visual.is_some(),
+            window_opacity: None,
+            fonts: HashMap::new(),
+            text_fonts: HashMap::new(),
+            icons: HashMap::new(),
         };
 
-        ret.init_window();
+        ret.init_window(argb_visual);
         ret.init_gc();
 
         let title = env!("CARGO_PKG_NAME");
@@ -106,7 +201,7 @@ impl Display {
             ..
         } = self;
         let screen_resources = screen_resources.get_or_insert_with(|| {
-            let sr_cookie = xcb::randr::get_screen_resources(&connection, *window);
+            let sr_cookie = xcb::randr::get_screen_resources(connection, *window);
             sr_cookie
                 .get_reply()
                 .expect("Could not get screen resources.")
@@ -141,20 +236,29 @@ impl Display {
         panic!("Pointer location was not on any screen.");
     }
 
-    fn configure_window(&mut self, screen_info: &ScreenInfo, global_config: &config::GlobalConfig) {
-        if self
-            .previous_screen
-            .map(|prev| &prev == screen_info)
-            .unwrap_or(false)
-        {
+    fn configure_window(
+        &mut self,
+        screen_info: &ScreenInfo,
+        global_config: &config::GlobalConfig,
+        reserved: config::Reserved,
+    ) {
+        let width = global_config.total_width(screen_info.width, reserved);
+        let height = global_config.total_height(screen_info.height, reserved);
+        let x = global_config.x(screen_info.width, reserved) + screen_info.x as u32;
+        let y = global_config.y(screen_info.height, reserved) + screen_info.y as u32;
+
+        // The geometry is compared instead of the screen since the config may have been reloaded.
+        let geometry = WindowGeometry {
+            width,
+            height,
+            x,
+            y,
+            radius: global_config.window_radius,
+        };
+        if self.previous_geometry == Some(geometry) {
             return;
         }
-        self.previous_screen = Some(*screen_info);
-
-        let width = global_config.total_width(screen_info.width);
-        let height = global_config.total_height(screen_info.height);
-        let x = global_config.x(screen_info.width) + screen_info.x as u32;
-        let y = global_config.y(screen_info.height) + screen_info.y as u32;
+        self.previous_geometry = Some(geometry);
 
         xcb::configure_window(
             &self.connection,
@@ -167,6 +271,377 @@ impl Display {
                 (xcb::CONFIG_WINDOW_STACK_MODE as u16, xcb::STACK_MODE_ABOVE),
             ],
         );
+        self.shape_window(width as u16, height as u16, global_config.window_radius);
+    }
+
+    /// Rounds the corners of the window by `radius` with the X Shape extension, or restores a
+    /// rectangular window if `radius` is 0.
+    fn shape_window(&self, width: u16, height: u16, radius: u32) {
+        if !self.shape {
+            if radius > 0 {
+                eprintln!("X Shape extension is not available, not rounding window corners.");
+            }
+            return;
+        }
+
+        let mask = if radius == 0 {
+            xcb::NONE
+        } else {
+            self.mask(
+                width,
+                height,
+                xcb::Rectangle::new(0, 0, width, height),
+                radius,
+            )
+        };
+        xcb::shape::mask(
+            &self.connection,
+            xcb::shape::SO_SET as u8,
+            xcb::shape::SK_BOUNDING as u8,
+            self.window,
+            0,
+            0,
+            mask,
+        );
+        if mask != xcb::NONE {
+            xcb::free_pixmap(&self.connection, mask);
+        }
+    }
+
+    /// Returns a `width` by `height` bitmap that is set inside `rectangle` with its corners rounded
+    /// by `radius`. The caller is responsible for freeing the bitmap.
+    fn mask(&self, width: u16, height: u16, rectangle: xcb::Rectangle, radius: u32) -> u32 {
+        let pixmap = self.connection.generate_id();
+        xcb::create_pixmap(&self.connection, 1, pixmap, self.window, width, height);
+        let gc = self.connection.generate_id();
+        xcb::create_gc(&self.connection, gc, pixmap, &[(xcb::GC_FOREGROUND, 0)]);
+        xcb::poly_fill_rectangle(
+            &self.connection,
+            pixmap,
+            gc,
+            &[xcb::Rectangle::new(0, 0, width, height)],
+        );
+        xcb::change_gc(&self.connection, gc, &[(xcb::GC_FOREGROUND, 1)]);
+        self.fill_rounded_rectangle(pixmap, gc, rectangle, radius);
+        xcb::free_gc(&self.connection, gc);
+        pixmap
+    }
+
+    /// Returns the pixel value of `color`, which is in `0xAARRGGBB`, with its alpha multiplied by
+    /// `opacity`. Compositing managers expect the color channels of a window with an alpha channel
+    /// to be premultiplied by alpha. Without an alpha channel, the alpha of `color` is ignored.
+    fn pixel(&self, color: u32, opacity: f64) -> u32 {
+        if !self.argb {
+            return color & 0x00ff_ffff;
+        }
+        let alpha = f64::from(color >> 24) / 255.0 * opacity;
+        config::interpolate_color(0, color | 0xff00_0000, alpha)
+    }
+
+    /// Sets `_NET_WM_WINDOW_OPACITY` to `opacity` if the window does not have an alpha channel, so
+    /// that a compositing manager started after the daemon can still make the window translucent.
+    fn set_window_opacity(&mut self, opacity: f64) {
+        if self.argb {
+            return;
+        }
+        let window_opacity = f64::round(opacity * f64::from(u32::MAX)) as u32;
+        if self.window_opacity == Some(window_opacity) {
+            return;
+        }
+        let atom = match intern_atom(&self.connection, "_NET_WM_WINDOW_OPACITY") {
+            Some(atom) => atom,
+            None => return,
+        };
+        self.window_opacity = Some(window_opacity);
+
+        // An opaque window has no opacity property.
+        if window_opacity == u32::MAX {
+            xcb::delete_property(&self.connection, self.window, atom);
+        } else {
+            xcb::change_property(
+                &self.connection,
+                xcb::PROP_MODE_REPLACE as u8,
+                self.window,
+                atom,
+                xcb::ATOM_CARDINAL,
+                32,
+                &[window_opacity],
+            );
+        }
+    }
+
+    /// Returns the core font named `name`, opening it if it has not been used before. A font that
+    /// fails to open is only reported once.
+    fn font(&mut self, name: &str) -> Option<Font> {
+        if let Some(font) = self.fonts.get(name) {
+            return *font;
+        }
+
+        let id = self.connection.generate_id();
+        let font = match xcb::open_font_checked(&self.connection, id, name).request_check() {
+            Ok(()) => {
+                let reply = xcb::query_font(&self.connection, id)
+                    .get_reply()
+                    .expect("Could not query font.");
+                Some(Font {
+                    id,
+                    ascent: reply.font_ascent() as u16,
+                    descent: reply.font_descent() as u16,
+                })
+            }
+            Err(_) => {
+                eprintln!("Error opening font `{}`, not drawing label.", name);
+                None
+            }
+        };
+        self.fonts.insert(name.to_owned(), font);
+        font
+    }
+
+    fn text_width(&self, font: Font, text: &[u8]) -> u16 {
+        let chars = text
+            .iter()
+            .map(|byte| xcb::Char2b::new(0, *byte))
+            .collect::<Vec<_>>();
+        xcb::query_text_extents(&self.connection, font.id, &chars)
+            .get_reply()
+            .map(|reply| reply.overall_width() as u16)
+            .unwrap_or(0)
+    }
+
+    /// Returns the label for `value`. The space reserved for the label fits the labels of both
+    /// `min` and the largest value so that the window does not resize as the value changes.
+    fn label(&mut self, value: f64, global_config: &config::GlobalConfig) -> Option<Content> {
+        let text = encode_latin1(&global_config.format_label(value)?);
+        let font = self.font(&global_config.label_font)?;
+        let width = self.text_width(font, &text);
+        let max_width = [global_config.min, global_config.max_value()]
+            .iter()
+            .filter_map(|value| global_config.format_label(*value))
+            .map(|label| self.text_width(font, &encode_latin1(&label)))
+            .fold(width, u16::max);
+        let height = font.ascent + font.descent;
+        Some(Content {
+            kind: ContentKind::Label { text, font },
+            position: global_config.label_position,
+            width,
+            height,
+            reserved_width: max_width,
+            reserved_height: height,
+        })
+    }
+
+    /// Returns the fontconfig font matching `name`, loading it if it has not been used before. A
+    /// font that fails to load is only reported once.
+    fn text_font(&mut self, name: &str) -> Option<&FontVec> {
+        self.text_fonts
+            .entry(name.to_owned())
+            .or_insert_with(|| match text::find_font(name) {
+                Ok(font) => Some(font),
+                Err(err) => {
+                    eprintln!("{}, not drawing text.", err);
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    fn text(&mut self, text: &str, global_config: &config::GlobalConfig) -> Option<Content> {
+        let font = self.text_font(&global_config.text_font)?;
+        let bitmap = text::render(font, global_config.text_size, text);
+        Some(Content {
+            position: global_config.text_position,
+            width: bitmap.width,
+            height: bitmap.height,
+            reserved_width: bitmap.width,
+            reserved_height: bitmap.height,
+            kind: ContentKind::Text(bitmap),
+        })
+    }
+
+    /// Returns the icon at `path`, loading it if it has not been used before. An icon that fails to
+    /// load is only reported once.
+    fn icon_file(&mut self, path: &Path) -> Option<&Icon> {
+        self.icons
+            .entry(path.to_owned())
+            .or_insert_with(|| match icon::load(path) {
+                Ok(icon) => Some(icon),
+                Err(err) => {
+                    eprintln!("{}, not drawing icon.", err);
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    /// Returns the icon of the profile, or its alternate icon if `alternate` is set and the profile
+    /// has one. An icon file takes precedence over a glyph.
+    fn icon(&mut self, alternate: bool, global_config: &config::GlobalConfig) -> Option<Content> {
+        let (path, glyph) = if alternate
+            && (global_config.alternate_icon.is_some()
+                || global_config.alternate_icon_glyph.is_some())
+        {
+            (
+                &global_config.alternate_icon,
+                &global_config.alternate_icon_glyph,
+            )
+        } else {
+            (&global_config.icon, &global_config.icon_glyph)
+        };
+        let icon = match (path, glyph) {
+            (Some(path), _) => self.icon_file(path)?.clone(),
+            (None, Some(glyph)) => {
+                let font = self.text_font(&global_config.icon_font)?;
+                Icon::Mask(text::render(font, global_config.icon_size, glyph))
+            }
+            (None, None) => return None,
+        };
+        let (width, height) = match icon {
+            Icon::Mask(ref bitmap) => (bitmap.width, bitmap.height),
+            Icon::Image(ref image) => (image.width, image.height),
+        };
+        Some(Content {
+            kind: ContentKind::Icon(icon),
+            position: global_config.icon_position,
+            width,
+            height,
+            reserved_width: width,
+            reserved_height: height,
+        })
+    }
+
+    /// Draws `content` centered in `area`.
+    fn draw_content(&self, color: u32, opacity: f64, content: &Content, area: xcb::Rectangle) {
+        let x = area.x() + (area.width() as i16 - content.width as i16) / 2;
+        let y = area.y() + (area.height() as i16 - content.height as i16) / 2;
+        match content.kind {
+            ContentKind::Label { ref text, font } => self.draw_label(color, text, font, x, y),
+            ContentKind::Text(ref bitmap) | ContentKind::Icon(Icon::Mask(ref bitmap)) => {
+                self.draw_bitmap(color, bitmap, x, y)
+            }
+            ContentKind::Icon(Icon::Image(ref image)) => self.draw_image(image, opacity, x, y),
+        }
+    }
+
+    /// Draws `text` with its top left corner at `x` and `y`.
+    fn draw_label(&self, color: u32, text: &[u8], font: Font, x: i16, y: i16) {
+        xcb::change_gc(
+            &self.connection,
+            self.gc,
+            &[(xcb::GC_FOREGROUND, color), (xcb::GC_FONT, font.id)],
+        );
+
+        // Each text item is its length, the horizontal offset from the previous item, and the text.
+        let mut items = Vec::new();
+        for chunk in text.chunks(MAX_TEXT_ITEM_LENGTH) {
+            items.push(chunk.len() as u8);
+            items.push(0);
+            items.extend(chunk);
+        }
+        let baseline = y + font.ascent as i16;
+        xcb::poly_text_8(&self.connection, self.window, self.gc, x, baseline, &items);
+    }
+
+    /// Draws `bitmap` with its top left corner at `x` and `y` by blending `color` over what is
+    /// already drawn in the window.
+    fn draw_bitmap(&self, color: u32, bitmap: &text::Bitmap, x: i16, y: i16) {
+        self.blend_area(x, y, bitmap.width, bitmap.height, |index, background| {
+            let coverage = bitmap.coverage[index];
+            if coverage == 0.0 {
+                background
+            } else {
+                config::interpolate_color(background, color, f64::from(coverage))
+            }
+        });
+    }
+
+    /// Draws `image` with its top left corner at `x` and `y` by blending it over what is already
+    /// drawn in the window according to its alpha channel.
+    fn draw_image(&self, image: &icon::Image, opacity: f64, x: i16, y: i16) {
+        self.blend_area(x, y, image.width, image.height, |index, background| {
+            let color = image.pixels[index];
+            let alpha = f64::from(color >> 24) / 255.0;
+            config::interpolate_color(background, self.pixel(color | 0xff00_0000, opacity), alpha)
+        });
+    }
+
+    /// Replaces each pixel in the area of the window with its top left corner at `x` and `y` by
+    /// `blend` of its index in the area and its current color. The area is clipped to the window,
+    /// since the contents outside of the window cannot be read.
+    fn blend_area<F>(&self, x: i16, y: i16, width: u16, height: u16, blend: F)
+    where
+        F: Fn(usize, u32) -> u32,
+    {
+        let visible = match self.previous_geometry.and_then(|geometry| {
+            clip_rectangle(
+                xcb::Rectangle::new(x, y, width, height),
+                geometry.width,
+                geometry.height,
+            )
+        }) {
+            Some(visible) => visible,
+            None => return,
+        };
+        let x_offset = (i32::from(visible.x()) - i32::from(x)) as usize;
+        let y_offset = (i32::from(visible.y()) - i32::from(y)) as usize;
+
+        let image = match xcb::get_image(
+            &self.connection,
+            xcb::IMAGE_FORMAT_Z_PIXMAP as u8,
+            self.window,
+            visible.x(),
+            visible.y(),
+            visible.width(),
+            visible.height(),
+            !0,
+        )
+        .get_reply()
+        {
+            Ok(image) => image,
+            Err(_) => {
+                eprintln!("Error reading window contents, not drawing content.");
+                return;
+            }
+        };
+        let mut data = image.data().to_vec();
+        let visible_width = usize::from(visible.width());
+        if data.len() != visible_width * usize::from(visible.height()) * 4 {
+            eprintln!("Expected 32 bits per pixel, not drawing content.");
+            return;
+        }
+
+        let msb_first =
+            self.connection.get_setup().image_byte_order() == xcb::IMAGE_ORDER_MSB_FIRST as u8;
+        for (index, pixel) in data.chunks_mut(4).enumerate() {
+            let bytes = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            let background = if msb_first {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            };
+            let row = y_offset + index / visible_width;
+            let column = x_offset + index % visible_width;
+            let blended = blend(row * usize::from(width) + column, background);
+            if msb_first {
+                pixel.copy_from_slice(&blended.to_be_bytes());
+            } else {
+                pixel.copy_from_slice(&blended.to_le_bytes());
+            }
+        }
+
+        xcb::put_image(
+            &self.connection,
+            xcb::IMAGE_FORMAT_Z_PIXMAP as u8,
+            self.window,
+            self.gc,
+            visible.width(),
+            visible.height(),
+            visible.x(),
+            visible.y(),
+            0,
+            image.depth(),
+            &data,
+        );
     }
 
     fn draw_rectangle(&self, color: u32, rectangle: xcb::Rectangle) {
@@ -174,88 +649,569 @@ impl Display {
         xcb::poly_fill_rectangle(&self.connection, self.window, self.gc, &[rectangle]);
     }
 
+    fn draw_rounded_rectangle(&self, color: u32, rectangle: xcb::Rectangle, radius: u32) {
+        xcb::change_gc(&self.connection, self.gc, &[(xcb::GC_FOREGROUND, color)]);
+        self.fill_rounded_rectangle(self.window, self.gc, rectangle, radius);
+    }
+
+    /// Fills `rectangle` in `drawable` with its corners rounded by `radius`, which is limited to
+    /// half of the shorter side. The rectangle is filled as a horizontal and a vertical rectangle
+    /// that overlap in the middle, and a circle in each corner.
+    fn fill_rounded_rectangle(
+        &self,
+        drawable: u32,
+        gc: u32,
+        rectangle: xcb::Rectangle,
+        radius: u32,
+    ) {
+        let (x, y, width, height) = (
+            rectangle.x(),
+            rectangle.y(),
+            rectangle.width(),
+            rectangle.height(),
+        );
+        let radius = radius.min(u32::from(width.min(height) / 2)) as u16;
+        if radius == 0 {
+            xcb::poly_fill_rectangle(&self.connection, drawable, gc, &[rectangle]);
+            return;
+        }
+
+        let diameter = radius * 2;
+        xcb::poly_fill_rectangle(
+            &self.connection,
+            drawable,
+            gc,
+            &[
+                xcb::Rectangle::new(x + radius as i16, y, width - diameter, height),
+                xcb::Rectangle::new(x, y + radius as i16, width, height - diameter),
+            ],
+        );
+        let right = x + (width - diameter) as i16;
+        let bottom = y + (height - diameter) as i16;
+        let circles = [(x, y), (right, y), (x, bottom), (right, bottom)]
+            .iter()
+            .map(|&(x, y)| xcb::Arc::new(x, y, diameter, diameter, 0, 360 * 64))
+            .collect::<Vec<_>>();
+        xcb::poly_fill_arc(&self.connection, drawable, gc, &circles);
+    }
+
     fn draw_bar(
         &self,
-        value: u8,
+        value: f64,
+        previous_value: Option<f64>,
+        contents: &[Content],
         screen_info: &ScreenInfo,
         global_config: &config::GlobalConfig,
         color_config: &config::ColorConfig,
     ) {
-        let mut x = 0;
-        let mut y = 0;
-        let mut width = global_config.total_width(screen_info.width) as u16;
-        let mut height = global_config.total_height(screen_info.height) as u16;
+        let opacity = global_config.opacity;
+        let color_config = color_config
+            .for_value(value)
+            .map_colors(|color| self.pixel(color, opacity));
+        let reserved = reserve(global_config, contents);
+        let total_width = global_config.total_width(screen_info.width, reserved) as u16;
+        let total_height = global_config.total_height(screen_info.height, reserved) as u16;
         self.draw_rectangle(
             color_config.background,
-            xcb::Rectangle::new(x, y, width, height),
+            xcb::Rectangle::new(0, 0, total_width, total_height),
         );
 
+        let (box_x, box_y) =
+            global_config.box_offset(screen_info.width, screen_info.height, reserved);
+        let mut x = box_x as i16;
+        let mut y = box_y as i16;
+        let mut width = global_config.box_width(screen_info.width) as u16;
+        let mut height = global_config.box_height(screen_info.height) as u16;
+        let (box_width, box_height) = (width, height);
+
         x += global_config.margin as i16;
         y += global_config.margin as i16;
         width -= global_config.margin as u16 * 2;
         height -= global_config.margin as u16 * 2;
-        self.draw_rectangle(
+        self.draw_rounded_rectangle(
             color_config.border,
             xcb::Rectangle::new(x, y, width, height),
+            global_config.border_radius,
         );
 
         x += global_config.border as i16;
         y += global_config.border as i16;
         width -= global_config.border as u16 * 2;
         height -= global_config.border as u16 * 2;
-        self.draw_rectangle(
+        // The inside of the border follows the curve of its outside.
+        self.draw_rounded_rectangle(
             color_config.background,
             xcb::Rectangle::new(x, y, width, height),
+            global_config
+                .border_radius
+                .saturating_sub(global_config.border),
         );
 
-        let height_diff =
-            f64::from(global_config.height(screen_info.height)) * (100 - value) as f64 / 100.0;
-        let width_diff =
-            f64::from(global_config.width(screen_info.width)) * (100 - value) as f64 / 100.0;
-
         x += global_config.padding as i16;
         y += global_config.padding as i16;
         width -= global_config.padding as u16 * 2;
         height -= global_config.padding as u16 * 2;
+        let bar = xcb::Rectangle::new(x, y, width, height);
 
-        match global_config.fill_direction {
-            config::Direction::Up => {
-                y += height_diff as i16;
-                height -= height_diff as u16;
-            }
-            config::Direction::Down => {
-                height -= height_diff as u16;
+        let replaced = contents
+            .iter()
+            .any(|content| matches!(content.position, config::LabelPosition::Replace));
+        if !replaced {
+            // The fill is clipped to the bar with its corners rounded.
+            let clip_mask = if global_config.fill_radius > 0 {
+                self.mask(total_width, total_height, bar, global_config.fill_radius)
+            } else {
+                xcb::NONE
+            };
+            xcb::change_gc(&self.connection, self.gc, &[(xcb::GC_CLIP_MASK, clip_mask)]);
+            self.draw_fill(value, previous_value, bar, global_config, &color_config);
+            if clip_mask != xcb::NONE {
+                xcb::change_gc(&self.connection, self.gc, &[(xcb::GC_CLIP_MASK, xcb::NONE)]);
+                xcb::free_pixmap(&self.connection, clip_mask);
             }
-            config::Direction::Left => {
-                x += width_diff as i16;
-                width -= width_diff as u16;
+        }
+
+        // Contents are placed outwards from the bar in order, each followed by a margin.
+        let margin = global_config.margin as i16;
+        let mut before = reserved.before as i16;
+        let mut after = if global_config.is_horizontal() {
+            box_x as i16 + box_width as i16
+        } else {
+            box_y as i16 + box_height as i16
+        };
+        for content in contents {
+            let (along, across) = if global_config.is_horizontal() {
+                (content.reserved_width, total_height)
+            } else {
+                (content.reserved_height, total_width)
+            };
+            let start = match content.position {
+                config::LabelPosition::Inside | config::LabelPosition::Replace => None,
+                config::LabelPosition::Before => {
+                    before -= along as i16;
+                    let start = before;
+                    before -= margin;
+                    Some(start)
+                }
+                config::LabelPosition::After => {
+                    let start = after;
+                    after += along as i16 + margin;
+                    Some(start)
+                }
+            };
+            let area = match start {
+                None => bar,
+                Some(start) if global_config.is_horizontal() => {
+                    xcb::Rectangle::new(start, 0, along, across)
+                }
+                Some(start) => xcb::Rectangle::new(0, start, across, along),
+            };
+            let color = match content.kind {
+                ContentKind::Label { .. } => color_config.label,
+                ContentKind::Text(_) => color_config.text,
+                ContentKind::Icon(_) => color_config.icon,
+            };
+            self.draw_content(color, opacity, content, area);
+        }
+    }
+
+    /// Draws the fill of `bar` for `value`, followed by the overflow and markers.
+    fn draw_fill(
+        &self,
+        value: f64,
+        previous_value: Option<f64>,
+        bar: xcb::Rectangle,
+        global_config: &config::GlobalConfig,
+        color_config: &config::ColorConfig,
+    ) {
+        // The fill spans from the origin to the value, so it is on the opposite side of the origin
+        // for values below it. Values above `max` fill the whole bar, and the excess is drawn on top
+        // of it.
+        let direction = global_config.fill_direction;
+        let length = f64::from(bar_length(direction, bar));
+        let fill_ratio = global_config.fill_ratio(value);
+        let origin_ratio = global_config.fill_ratio(global_config.origin());
+        let value_ratio = fill_ratio.clamp(0.0, 1.0);
+        let sections = if value_ratio < origin_ratio {
+            fill_sections(global_config, length, value_ratio, origin_ratio)
+        } else {
+            fill_sections(global_config, length, origin_ratio, value_ratio)
+        };
+        for (start, end) in sections {
+            match color_config.gradient_mode {
+                config::GradientMode::Bar if !color_config.gradient.is_empty() => {
+                    self.draw_gradient(color_config, global_config, bar, start, end)
+                }
+                _ => self.draw_rectangle(
+                    color_config.foreground,
+                    bar_section(direction, bar, start, end),
+                ),
             }
-            config::Direction::Right => {
-                width -= width_diff as u16;
+        }
+
+        if fill_ratio > 1.0 {
+            let overflow_ratio = (fill_ratio - 1.0).min(1.0);
+            for (start, end) in fill_sections(global_config, length, 0.0, overflow_ratio) {
+                self.draw_rectangle(
+                    color_config.overflow,
+                    bar_section(direction, bar, start, end),
+                );
             }
         }
 
-        self.draw_rectangle(
-            color_config.foreground,
-            xcb::Rectangle::new(x, y, width, height),
-        );
+        // Markers are drawn on top of the fill, with the most specific markers drawn last.
+        let marker_size = global_config.marker_size as u16;
+        for tick in global_config.tick_values(length as usize) {
+            let ratio = global_config.fill_ratio(tick);
+            self.draw_marker(color_config.tick, direction, bar, ratio, marker_size);
+        }
+        for marker in &global_config.markers {
+            let ratio = global_config.fill_ratio(*marker);
+            self.draw_marker(color_config.marker, direction, bar, ratio, marker_size);
+        }
+        if let Some(previous_value) = previous_value.filter(|_| global_config.ghost) {
+            let ratio = global_config.fill_ratio(previous_value);
+            self.draw_marker(color_config.ghost, direction, bar, ratio, marker_size);
+        }
+        if let config::FillMode::Center = global_config.fill_mode {
+            self.draw_marker(
+                color_config.center,
+                direction,
+                bar,
+                origin_ratio,
+                marker_size,
+            );
+        }
+    }
+
+    /// Draws a line of `size` across `bar` at `ratio` of its length, keeping the line within
+    /// `bar`.
+    fn draw_marker(
+        &self,
+        color: u32,
+        direction: config::Direction,
+        bar: xcb::Rectangle,
+        ratio: f64,
+        size: u16,
+    ) {
+        let length = bar_length(direction, bar);
+        let size = size.min(length);
+        let offset = (f64::from(length) * ratio.clamp(0.0, 1.0)).round() as u16;
+        let start = offset.saturating_sub(size / 2).min(length - size);
+        self.draw_rectangle(color, bar_section(direction, bar, start, start + size));
+    }
+
+    /// Draws the section of `bar` between `start` and `end` in strips perpendicular to the fill
+    /// direction, colored by the gradient at their position along `bar`. Neighbouring strips with
+    /// the same color are merged.
+    fn draw_gradient(
+        &self,
+        color_config: &config::ColorConfig,
+        global_config: &config::GlobalConfig,
+        bar: xcb::Rectangle,
+        mut start: u16,
+        end: u16,
+    ) {
+        let direction = global_config.fill_direction;
+        let bar_length = bar_length(direction, bar);
+        let color_at = |offset: u16| {
+            let ratio = f64::from(offset) / f64::from(bar_length);
+            color_config
+                .gradient_color(global_config.min + ratio * (global_config.max - global_config.min))
+        };
+        while start < end {
+            let color = color_at(start);
+            let mut strip_end = start + 1;
+            while strip_end < end && color_at(strip_end) == color {
+                strip_end += 1;
+            }
+            self.draw_rectangle(color, bar_section(direction, bar, start, strip_end));
+            start = strip_end;
+        }
     }
 
     pub fn show(
         &mut self,
-        value: u8,
+        value: f64,
+        previous_value: Option<f64>,
+        options: &server::ShowOptions,
         global_config: &config::GlobalConfig,
         color_config: &config::ColorConfig,
     ) {
         let screen_info = self.get_size_and_offset();
+        let mut contents = Vec::new();
+        contents.extend(self.label(value, global_config));
+        if let Some(ref text) = options.text {
+            contents.extend(self.text(text, global_config));
+        }
+        contents.extend(self.icon(options.alternate, global_config));
+        self.set_window_opacity(global_config.opacity);
         xcb::map_window(&self.connection, self.window);
-        self.configure_window(&screen_info, global_config);
-        self.draw_bar(value, &screen_info, global_config, color_config);
+        self.configure_window(
+            &screen_info,
+            global_config,
+            reserve(global_config, &contents),
+        );
+        self.draw_bar(
+            value,
+            previous_value,
+            &contents,
+            &screen_info,
+            global_config,
+            color_config,
+        );
         self.connection.flush();
     }
 
+    /// Forgets the fonts and icons that were loaded, including the ones that failed to load, so
+    /// that changes to their files take effect when the config is reloaded.
+    pub fn clear_caches(&mut self) {
+        for font in self.fonts.values().flatten() {
+            xcb::close_font(&self.connection, font.id);
+        }
+        self.fonts.clear();
+        self.text_fonts.clear();
+        self.icons.clear();
+    }
+
     pub fn hide(&self) {
         xcb::unmap_window(&self.connection, self.window);
         self.connection.flush();
     }
 }
+
+fn intern_atom(connection: &xcb::Connection, name: &str) -> Option<u32> {
+    xcb::intern_atom(connection, false, name)
+        .get_reply()
+        .ok()
+        .map(|reply| reply.atom())
+}
+
+/// Returns a 32-bit TrueColor visual of the screen if a compositing manager is running, which is
+/// the case if the `_NET_WM_CM_S<screen>` selection has an owner.
+fn find_argb_visual(connection: &xcb::Connection, screen_index: usize) -> Option<u32> {
+    let selection = intern_atom(connection, &format!("_NET_WM_CM_S{}", screen_index))?;
+    let owner = xcb::get_selection_owner(connection, selection)
+        .get_reply()
+        .ok()?
+        .owner();
+    if owner == xcb::NONE {
+        return None;
+    }
+
+    let screen = connection.get_setup().roots().nth(screen_index)?;
+    let depth = screen.allowed_depths().find(|depth| depth.depth() == 32)?;
+    let visual = depth
+        .visuals()
+        .find(|visual| visual.class() == xcb::VISUAL_CLASS_TRUE_COLOR as u8)?;
+    Some(visual.visual_id())
+}
+
+/// Returns the part of `rectangle` inside a window of `window_width` by `window_height`, or `None`
+/// if no part of it is inside the window.
+fn clip_rectangle(
+    rectangle: xcb::Rectangle,
+    window_width: u32,
+    window_height: u32,
+) -> Option<xcb::Rectangle> {
+    let left = i64::from(rectangle.x()).max(0);
+    let top = i64::from(rectangle.y()).max(0);
+    let right =
+        (i64::from(rectangle.x()) + i64::from(rectangle.width())).min(i64::from(window_width));
+    let bottom =
+        (i64::from(rectangle.y()) + i64::from(rectangle.height())).min(i64::from(window_height));
+    if left >= right || top >= bottom {
+        return None;
+    }
+    Some(xcb::Rectangle::new(
+        left as i16,
+        top as i16,
+        (right - left) as u16,
+        (bottom - top) as u16,
+    ))
+}
+
+/// Returns the space to reserve beside the bar for `contents`.
+fn reserve(global_config: &config::GlobalConfig, contents: &[Content]) -> config::Reserved {
+    let mut reserved = config::Reserved::default();
+    for content in contents {
+        let (along, across) = if global_config.is_horizontal() {
+            (content.reserved_width, content.reserved_height)
+        } else {
+            (content.reserved_height, content.reserved_width)
+        };
+        let size = u32::from(along) + global_config.margin;
+        match content.position {
+            config::LabelPosition::Inside | config::LabelPosition::Replace => continue,
+            config::LabelPosition::Before => reserved.before += size,
+            config::LabelPosition::After => reserved.after += size,
+        }
+        reserved.thickness = reserved.thickness.max(u32::from(across));
+    }
+    reserved
+}
+
+/// Encodes `text` in Latin-1, which is the encoding of core fonts, replacing characters that cannot
+/// be encoded with `?`.
+fn encode_latin1(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+        .collect()
+}
+
+/// Returns the length of `bar` along `direction`.
+fn bar_length(direction: config::Direction, bar: xcb::Rectangle) -> u16 {
+    match direction {
+        config::Direction::Up | config::Direction::Down => bar.height(),
+        config::Direction::Left | config::Direction::Right => bar.width(),
+    }
+}
+
+/// Returns the offsets along a bar of `length` to fill for the fraction of the bar between `start`
+/// and `end`. A continuous bar has a single section, while a segmented bar has a section for each
+/// segment that is filled according to `segment_fill`.
+fn fill_sections(
+    global_config: &config::GlobalConfig,
+    length: f64,
+    start: f64,
+    end: f64,
+) -> Vec<(u16, u16)> {
+    let to_offset = |position: f64| position.clamp(0.0, length).round() as u16;
+    if global_config.segments == 0 {
+        return vec![(to_offset(length * start), to_offset(length * end))];
+    }
+
+    let segments = f64::from(global_config.segments);
+    let gap = f64::from(global_config.segment_gap);
+    let segment_length = (length - gap * (segments - 1.0)) / segments;
+    // There is nothing to draw if the gaps take up the whole bar.
+    if segment_length <= 0.0 {
+        return Vec::new();
+    }
+    let mut sections = Vec::new();
+    for index in 0..global_config.segments {
+        let segment_start = f64::from(index) / segments;
+        let segment_end = f64::from(index + 1) / segments;
+        let filled = (end.min(segment_end) - start.max(segment_start)) * segments;
+        if filled <= 0.0 {
+            continue;
+        }
+
+        // The filled fraction of the segment, relative to the start of the segment.
+        let (fill_start, fill_end) = match global_config.segment_fill {
+            config::SegmentFill::Partial => (
+                (start.max(segment_start) - segment_start) * segments,
+                (end.min(segment_end) - segment_start) * segments,
+            ),
+            config::SegmentFill::Floor if filled < 1.0 - SEGMENT_FILL_TOLERANCE => continue,
+            config::SegmentFill::Round if filled < 0.5 => continue,
+            _ => (0.0, 1.0),
+        };
+        let offset = f64::from(index) * (segment_length + gap);
+        sections.push((
+            to_offset(offset + segment_length * fill_start),
+            to_offset(offset + segment_length * fill_end),
+        ));
+    }
+    sections
+}
+
+/// Returns the section of `bar` between `start` and `end`, which are offsets from the side of the
+/// bar that the fill starts from.
+fn bar_section(
+    direction: config::Direction,
+    bar: xcb::Rectangle,
+    start: u16,
+    end: u16,
+) -> xcb::Rectangle {
+    let length = bar_length(direction, bar);
+    match direction {
+        config::Direction::Up => xcb::Rectangle::new(
+            bar.x(),
+            bar.y() + (length - end) as i16,
+            bar.width(),
+            end - start,
+        ),
+        config::Direction::Down => {
+            xcb::Rectangle::new(bar.x(), bar.y() + start as i16, bar.width(), end - start)
+        }
+        config::Direction::Left => xcb::Rectangle::new(
+            bar.x() + (length - end) as i16,
+            bar.y(),
+            end - start,
+            bar.height(),
+        ),
+        config::Direction::Right => {
+            xcb::Rectangle::new(bar.x() + start as i16, bar.y(), end - start, bar.height())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(rectangle: xcb::Rectangle) -> (i16, i16, u16, u16) {
+        (
+            rectangle.x(),
+            rectangle.y(),
+            rectangle.width(),
+            rectangle.height(),
+        )
+    }
+
+    #[test]
+    fn fills_sections() {
+        let sections = |extra: &str, length, end| {
+            let profile_configs = config::tests::parse(extra).unwrap();
+            fill_sections(&profile_configs["volume"].global_config, length, 0.0, end)
+        };
+        assert_eq!(sections("", 100.0, 0.6), vec![(0, 60)]);
+
+        let segmented = |segment_fill| {
+            format!(
+                "segments = 4\nsegment_gap = 2\nsegment_fill = \"{}\"",
+                segment_fill
+            )
+        };
+        assert_eq!(
+            sections(&segmented("partial"), 106.0, 0.6),
+            vec![(0, 25), (27, 52), (54, 64)],
+        );
+        assert_eq!(
+            sections(&segmented("round"), 106.0, 0.6),
+            vec![(0, 25), (27, 52)],
+        );
+        assert_eq!(
+            sections(&segmented("round"), 106.0, 0.7),
+            vec![(0, 25), (27, 52), (54, 79)],
+        );
+        assert_eq!(
+            sections(&segmented("floor"), 106.0, 0.7),
+            vec![(0, 25), (27, 52)],
+        );
+        assert_eq!(
+            sections(&segmented("ceil"), 106.0, 0.6),
+            vec![(0, 25), (27, 52), (54, 79)],
+        );
+
+        // The gaps are wider than the bar.
+        assert_eq!(
+            sections("segments = 20\nsegment_gap = 10", 100.0, 1.0),
+            Vec::new(),
+        );
+    }
+
+    #[test]
+    fn clips_rectangles_to_the_window() {
+        let clip = |x, y, width, height| {
+            clip_rectangle(xcb::Rectangle::new(x, y, width, height), 100, 50).map(bounds)
+        };
+        assert_eq!(clip(10, 10, 20, 20), Some((10, 10, 20, 20)));
+        assert_eq!(clip(-30, 10, 200, 20), Some((0, 10, 100, 20)));
+        assert_eq!(clip(90, -5, 20, 20), Some((90, 0, 10, 15)));
+        assert_eq!(clip(-30, 10, 20, 20), None);
+        assert_eq!(clip(100, 10, 20, 20), None);
+        assert_eq!(clip(10, 10, 0, 20), None);
+    }
+}
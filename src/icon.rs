@@ -0,0 +1,195 @@
+use crate::text::Bitmap;
+use crate::{Error, Result};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::path::Path;
+
+/// An image where each pixel is a color in `0xAARRGGBB`.
+#[derive(Clone)]
+pub struct Image {
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<u32>,
+}
+
+#[derive(Clone)]
+pub enum Icon {
+    /// An icon drawn in the `icon` color of the color profile.
+    Mask(Bitmap),
+    /// An icon drawn in its own colors.
+    Image(Image),
+}
+
+/// Loads the icon at `path`. Files with the `xbm` extension are loaded as X bitmaps and all other
+/// files are loaded as PNG images.
+pub fn load(path: &Path) -> Result<Icon> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("xbm") => load_xbm(path).map(Icon::Mask),
+        _ => load_png(path).map(Icon::Image),
+    }
+}
+
+fn load_png(path: &Path) -> Result<Image> {
+    let context = || format!("reading icon `{}`", path.display());
+    let file = File::open(path).map_err(|err| Error::new(context(), &err))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|err| Error::new(context(), &err))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|err| Error::new(context(), &err))?;
+    let (width, height) = match (u16::try_from(info.width), u16::try_from(info.height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(Error::from_description(
+                context(),
+                "Expected the image to be at most 65535 pixels wide and high.",
+            ))
+        }
+    };
+
+    let channels = info.color_type.samples();
+    let pixels = buffer[..info.buffer_size()]
+        .chunks(channels)
+        .map(|pixel| {
+            let (red, green, blue, alpha) = match *pixel {
+                [gray] => (gray, gray, gray, 255),
+                [gray, alpha] => (gray, gray, gray, alpha),
+                [red, green, blue] => (red, green, blue, 255),
+                [red, green, blue, alpha] => (red, green, blue, alpha),
+                _ => (0, 0, 0, 0),
+            };
+            u32::from_be_bytes([alpha, red, green, blue])
+        })
+        .collect();
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Loads an X bitmap, which is C source defining `<name>_width`, `<name>_height`, and an array of
+/// bytes where each row is padded to a whole byte and the least significant bit is leftmost.
+fn load_xbm(path: &Path) -> Result<Bitmap> {
+    let context = || format!("reading icon `{}`", path.display());
+    let source = fs::read_to_string(path).map_err(|err| Error::new(context(), &err))?;
+
+    let define = |suffix: &str| {
+        source
+            .lines()
+            .filter_map(|line| {
+                let mut tokens = line.split_whitespace();
+                match (tokens.next(), tokens.next(), tokens.next()) {
+                    (Some("#define"), Some(name), Some(value)) if name.ends_with(suffix) => {
+                        value.parse::<u16>().ok()
+                    }
+                    _ => None,
+                }
+            })
+            .next()
+            .ok_or_else(|| {
+                Error::from_description(
+                    context(),
+                    format!("Expected `#define <name>{} <size>`.", suffix),
+                )
+            })
+    };
+    let width = define("_width")?;
+    let height = define("_height")?;
+
+    let data = source
+        .find('{')
+        .and_then(|start| Some((start, source[start..].find('}')? + start)))
+        .map(|(start, end)| &source[start + 1..end])
+        .ok_or_else(|| Error::from_description(context(), "Expected an array of bytes."))?;
+    let bytes = data
+        .split(',')
+        .map(str::trim)
+        .filter(|byte| !byte.is_empty())
+        .map(|byte| {
+            let digits = byte.trim_start_matches("0x").trim_start_matches("0X");
+            u8::from_str_radix(digits, 16).map_err(|err| Error::new(context(), &err))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let row_length = usize::from(width).div_ceil(8);
+    if bytes.len() < row_length * usize::from(height) {
+        return Err(Error::from_description(
+            context(),
+            format!(
+                "Expected {} bytes for a {}x{} bitmap.",
+                row_length * usize::from(height),
+                width,
+                height,
+            ),
+        ));
+    }
+    let mut coverage = Vec::with_capacity(usize::from(width) * usize::from(height));
+    for row in bytes.chunks(row_length.max(1)).take(usize::from(height)) {
+        for x in 0..usize::from(width) {
+            coverage.push(f32::from((row[x / 8] >> (x % 8)) & 1));
+        }
+    }
+    Ok(Bitmap {
+        width,
+        height,
+        coverage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn load_xbm_source(name: &str, source: &str) -> Result<Bitmap> {
+        let path = env::temp_dir().join(format!("robar-icon-test-{}-{}.xbm", process::id(), name));
+        fs::write(&path, source).unwrap();
+        let bitmap = load_xbm(&path);
+        fs::remove_file(&path).unwrap();
+        bitmap
+    }
+
+    #[test]
+    fn loads_xbm_least_significant_bit_first() {
+        let bitmap = load_xbm_source(
+            "valid",
+            "#define icon_width 10\n\
+             #define icon_height 2\n\
+             static unsigned char icon_bits[] = {\n\
+                0x01, 0x02, 0x80, 0x01 };\n",
+        )
+        .unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (10, 2));
+        let mut expected = vec![0.0; 20];
+        for index in &[0, 9, 17, 18] {
+            expected[*index] = 1.0;
+        }
+        assert_eq!(bitmap.coverage, expected);
+    }
+
+    #[test]
+    fn rejects_xbm_with_missing_bytes() {
+        let result = load_xbm_source(
+            "short",
+            "#define icon_width 10\n\
+             #define icon_height 3\n\
+             static unsigned char icon_bits[] = { 0x01, 0x02, 0x80, 0x01 };\n",
+        );
+        match result {
+            Ok(_) => panic!("Expected bitmap to be invalid."),
+            Err(err) => assert!(
+                err.to_string()
+                    .contains("Expected 6 bytes for a 10x3 bitmap."),
+                "{}",
+                err
+            ),
+        }
+    }
+}
@@ -1,17 +1,19 @@
 mod client;
 mod config;
 mod display;
+mod icon;
 mod server;
+mod text;
 
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use serde_derive::{Deserialize, Serialize};
 use std::error;
 use std::fmt;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::process;
 use std::result;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Error {
     context: String,
     description: String,
@@ -59,12 +61,52 @@ impl fmt::Display for Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// The exit status of `robar status` when the daemon is not running, following the LSB init script
+/// convention.
+const NOT_RUNNING_EXIT_CODE: i32 = 3;
+
+fn socket_path(matches: &ArgMatches<'_>) -> PathBuf {
+    matches
+        .value_of("socket")
+        .map(PathBuf::from)
+        .unwrap_or_else(server::default_socket_path)
+}
+
+fn show_options(matches: &ArgMatches<'_>) -> Result<server::ShowOptions> {
+    let timeout = match matches.value_of("timeout") {
+        Some(timeout) => Some(
+            timeout
+                .parse()
+                .map_err(|err| Error::new("parsing `timeout`", &err))?,
+        ),
+        None => None,
+    };
+    let priority = match matches.value_of("priority") {
+        Some(priority) => priority.parse()?,
+        None => server::Priority::default(),
+    };
+    Ok(server::ShowOptions {
+        timeout,
+        priority,
+        text: matches.value_of("text").map(String::from),
+        alternate: matches.is_present("alternate"),
+    })
+}
+
 fn run() -> Result<()> {
     let matches = App::new("robar")
         .setting(AppSettings::ArgRequiredElseHelp)
         .version(env!("CARGO_PKG_VERSION"))
         .author("Jeffrey Xiao <jeffrey.xiao1998@gmail.com>")
         .about("A simple, but flexible system overlay bar for the X Window System (X11).")
+        .arg(
+            Arg::with_name("socket")
+                .help("Path to the socket of the daemon.")
+                .takes_value(true)
+                .long("socket")
+                .env("ROBAR_SOCKET")
+                .global(true),
+        )
         .subcommand(
             SubCommand::with_name("start")
                 .about("Starts daemon that listens to requests.")
@@ -73,12 +115,26 @@ fn run() -> Result<()> {
                         .help("Path to configuration file.")
                         .takes_value(true)
                         .short("c")
-                        .long("config"),
+                        .long("config")
+                        .env("ROBAR_CONFIG"),
+                )
+                .arg(
+                    Arg::with_name("watch")
+                        .help("Reload the configuration file whenever it changes.")
+                        .short("w")
+                        .long("watch"),
+                )
+                .arg(
+                    Arg::with_name("verbose")
+                        .help("Print which configuration file is used.")
+                        .short("v")
+                        .long("verbose"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("show")
                 .about("Shows bar with a specific value and in a specific color profile.")
+                .setting(AppSettings::AllowNegativeNumbers)
                 .arg(
                     Arg::with_name("profile")
                         .help("The color profile to use.")
@@ -87,49 +143,192 @@ fn run() -> Result<()> {
                 )
                 .arg(
                     Arg::with_name("value")
-                        .help("The value of the bar.")
-                        .index(2)
-                        .required(true),
+                        .help(
+                            "The value of the bar, or a change to the last value in the color \
+                             profile if prefixed with `+` or `-`. Prefix a negative value with `=` \
+                             to show it as is. If omitted, the last value is shown again.",
+                        )
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .help(
+                            "The number of milliseconds before the bar is hidden, overriding the \
+                             config. If the timeout is 0, then the bar persists until hidden.",
+                        )
+                        .takes_value(true)
+                        .short("t")
+                        .long("timeout"),
+                )
+                .arg(
+                    Arg::with_name("priority")
+                        .help(
+                            "The priority of the bar. A visible bar is not replaced by one with a \
+                             lower priority until it is hidden.",
+                        )
+                        .takes_value(true)
+                        .possible_values(&["low", "normal", "high"])
+                        .short("p")
+                        .long("priority"),
+                )
+                .arg(
+                    Arg::with_name("text")
+                        .help("A message to draw with the bar.")
+                        .takes_value(true)
+                        .long("text"),
+                )
+                .arg(
+                    Arg::with_name("alternate")
+                        .help("Draw the alternate icon, such as when the volume is muted.")
+                        .long("alternate"),
+                )
+                .arg(
+                    Arg::with_name("no-wait")
+                        .help("Do not wait for the daemon to handle the request.")
+                        .long("no-wait"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("show-stream")
-                .about("Shows bar using lines from standard input in the form of `profile value`"),
+                .about("Shows bar using lines from standard input in the form of `profile value`")
+                .arg(
+                    Arg::with_name("timeout")
+                        .help(
+                            "The number of milliseconds before the bar is hidden, overriding the \
+                             config. If the timeout is 0, then the bar persists until hidden.",
+                        )
+                        .takes_value(true)
+                        .short("t")
+                        .long("timeout"),
+                )
+                .arg(
+                    Arg::with_name("priority")
+                        .help(
+                            "The priority of the bar. A visible bar is not replaced by one with a \
+                             lower priority until it is hidden.",
+                        )
+                        .takes_value(true)
+                        .possible_values(&["low", "normal", "high"])
+                        .short("p")
+                        .long("priority"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hide").about("Hides the bar.").arg(
+                Arg::with_name("no-wait")
+                    .help("Do not wait for the daemon to handle the request.")
+                    .long("no-wait"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("reload")
+                .about("Reloads the configuration file of the daemon.")
+                .arg(
+                    Arg::with_name("no-wait")
+                        .help("Do not wait for the daemon to handle the request.")
+                        .long("no-wait"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about(
+                    "Prints the status of the daemon. Exits with status 3 if the daemon is not \
+                     running.",
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .help("Print the status as JSON.")
+                        .long("json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("subscribe")
+                .about("Prints a line for every event handled by the daemon.")
+                .arg(
+                    Arg::with_name("json")
+                        .help("Print each event as JSON.")
+                        .long("json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("toggle")
+                .about("Hides the bar if it is visible, otherwise shows the last value again.")
+                .arg(
+                    Arg::with_name("profile")
+                        .help("The color profile to use. Defaults to the last color profile used.")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("no-wait")
+                        .help("Do not wait for the daemon to handle the request.")
+                        .long("no-wait"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stop").about("Stops daemon.").arg(
+                Arg::with_name("no-wait")
+                    .help("Do not wait for the daemon to handle the request.")
+                    .long("no-wait"),
+            ),
         )
-        .subcommand(SubCommand::with_name("hide").about("Hides the bar."))
-        .subcommand(SubCommand::with_name("stop").about("Stops daemon."))
         .get_matches();
 
     match matches.subcommand() {
         ("start", Some(matches)) => {
             let config_path = match matches.value_of("config") {
                 Some(config) => PathBuf::from(config),
-                None => {
-                    let xdg_config_home = option_env!("XDG_CONFIG_HOME");
-                    let config_home_dir = format!("{}/{}", env!("HOME"), ".config");
-                    Path::new(xdg_config_home.unwrap_or(&config_home_dir))
-                        .join(env!("CARGO_PKG_NAME"))
-                        .join(format!("{}.toml", env!("CARGO_PKG_NAME")))
-                }
+                None => config::find_config_path()?,
             };
-            let (global_config, color_configs) = config::parse_config(config_path)?;
+            if matches.is_present("verbose") {
+                println!("Using config `{}`.", config_path.display());
+            }
+            let profile_configs = config::parse_config(&config_path)?;
             let mut display = display::Display::new().unwrap();
-            server::start_server(&mut display, &global_config, &color_configs)
+            server::start_server(
+                &mut display,
+                &socket_path(matches),
+                &config_path,
+                matches.is_present("watch"),
+                profile_configs,
+            )
         }
         ("show", Some(matches)) => client::show(
+            &socket_path(matches),
             matches
                 .value_of("profile")
                 .expect("Expected `profile` to exist.")
                 .to_owned(),
-            matches
-                .value_of("value")
-                .expect("Expected `value` to exist.")
-                .parse()
-                .map_err(|err| Error::new("parsing `value`", &err))?,
+            matches.value_of("value"),
+            show_options(matches)?,
+            !matches.is_present("no-wait"),
+        ),
+        ("toggle", Some(matches)) => client::toggle(
+            &socket_path(matches),
+            matches.value_of("profile").map(String::from),
+            !matches.is_present("no-wait"),
         ),
-        ("show-stream", Some(_)) => client::show_stream(),
-        ("hide", Some(_)) => client::hide(),
-        ("stop", Some(_)) => client::stop(),
+        ("show-stream", Some(matches)) => {
+            client::show_stream(&socket_path(matches), show_options(matches)?)
+        }
+        ("hide", Some(matches)) => {
+            client::hide(&socket_path(matches), !matches.is_present("no-wait"))
+        }
+        ("reload", Some(matches)) => {
+            client::reload(&socket_path(matches), !matches.is_present("no-wait"))
+        }
+        ("status", Some(matches)) => {
+            if !client::status(&socket_path(matches), matches.is_present("json"))? {
+                eprintln!("The daemon is not running.");
+                process::exit(NOT_RUNNING_EXIT_CODE);
+            }
+            Ok(())
+        }
+        ("subscribe", Some(matches)) => {
+            client::subscribe(&socket_path(matches), matches.is_present("json"))
+        }
+        ("stop", Some(matches)) => {
+            client::stop(&socket_path(matches), !matches.is_present("no-wait"))
+        }
         _ => Ok(()),
     }
 }
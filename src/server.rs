@@ -1,119 +1,687 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::env;
 use std::fs;
-use std::io::Read;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
-use std::sync::mpsc::{channel, RecvError, RecvTimeoutError};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, RecvError, RecvTimeoutError, Sender};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use bincode::deserialize;
+use bincode::{deserialize, serialize};
+use inotify::{Inotify, WatchMask};
+use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
 
 use crate::config;
 use crate::display;
 use crate::{Error, Result};
 
-pub const MAX_REQUEST_SIZE: usize = 32;
-pub const SOCKET_PATH: &str = "/tmp/robar";
-pub const END_OF_REQUEST_SEPARATOR: u8 = 13;
+/// The version of the protocol spoken over the socket. It must be incremented whenever the
+/// encoding of an existing message changes.
+pub const PROTOCOL_VERSION: u32 = 6;
+
+const SOCKET_DIR_MODE: u32 = 0o700;
+const FRAME_HEADER_SIZE: usize = 4;
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// The first message sent by the server on every connection. Its layout must stay the same across
+/// protocol versions so that any client can tell which daemon it is talking to.
+#[derive(Serialize, Deserialize)]
+pub struct Hello {
+    pub version: String,
+    pub protocol_version: u32,
+    pub requests: Vec<String>,
+}
+
+impl Hello {
+    fn new() -> Self {
+        Hello {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            protocol_version: PROTOCOL_VERSION,
+            requests: Request::KINDS.iter().map(|kind| kind.to_string()).collect(),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub enum Request {
-    Show { profile: String, value: u8 },
-    Hide,
+    // `Stop` must remain the first variant so that a daemon speaking a different protocol version
+    // can still be stopped.
     Stop,
+    Show {
+        profile: String,
+        value: f64,
+        options: ShowOptions,
+    },
+    Hide,
     Empty,
+    Reload,
+    Status,
+    Subscribe,
+    Adjust {
+        profile: String,
+        delta: f64,
+        options: ShowOptions,
+    },
+    Toggle {
+        profile: Option<String>,
+    },
 }
 
-pub struct RequestBuffer {
-    pending: Option<(usize, usize, Vec<u8>)>,
-    next: Vec<u8>,
+/// The priority of a `Show` request. A visible bar is not replaced by a request with a lower
+/// priority until it is hidden.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
 }
 
-impl RequestBuffer {
-    pub fn new() -> Self {
-        Self {
-            pending: None,
-            next: vec![0; MAX_REQUEST_SIZE],
+impl FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(priority: &str) -> Result<Self> {
+        match priority {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(Error::from_description(
+                "parsing priority",
+                format!(
+                    "Expected `low`, `normal`, or `high`, but got `{}`.",
+                    priority
+                ),
+            )),
         }
     }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ShowOptions {
+    /// The number of milliseconds before the bar is hidden, overriding `timeout` in the config. If
+    /// the timeout is 0, then the bar persists until it is hidden.
+    pub timeout: Option<u64>,
+    pub priority: Priority,
+    /// A message to draw with the bar.
+    pub text: Option<String>,
+    /// Whether to draw the alternate icon, such as when the volume is muted.
+    pub alternate: bool,
+}
+
+/// Defines `Request::KINDS` and `Request::kind` from a single table. The match in `kind` is
+/// exhaustive, so every request must be in the table and is therefore advertised in the handshake.
+macro_rules! request_kinds {
+    ($($variant:ident => $kind:expr,)*) => {
+        impl Request {
+            pub const KINDS: &'static [&'static str] = &[$($kind,)*];
 
-    pub fn read_request(&mut self, stream: &mut UnixStream) -> Result<Option<Vec<u8>>> {
-        let next_end = stream
-            .read(self.next.as_mut_slice())
-            .map_err(|err| Error::new("reading from socket", &err))?;
-        if next_end == 0 {
-            return Ok(None);
+            pub fn kind(&self) -> &'static str {
+                match self {
+                    $(Request::$variant { .. } => $kind,)*
+                }
+            }
         }
-        let end_index = self
-            .next
-            .iter()
-            .position(|b| *b == END_OF_REQUEST_SEPARATOR)
-            .ok_or_else(|| {
-                Error::from_description(
+    };
+}
+
+request_kinds! {
+    Stop => "stop",
+    Show => "show",
+    Hide => "hide",
+    Empty => "empty",
+    Reload => "reload",
+    Status => "status",
+    Subscribe => "subscribe",
+    Adjust => "adjust",
+    Toggle => "toggle",
+}
+
+/// The reply sent by the server after handling each request.
+#[derive(Serialize, Deserialize)]
+pub enum Response {
+    Ok,
+    Error(Error),
+    Status(Status),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    pub pid: u32,
+    pub config_path: PathBuf,
+    pub visible: bool,
+    pub profile: Option<String>,
+    pub value: Option<f64>,
+    pub last_values: BTreeMap<String, f64>,
+    pub uptime_secs: u64,
+}
+
+impl From<Result<()>> for Response {
+    fn from(result: Result<()>) -> Self {
+        match result {
+            Ok(()) => Response::Ok,
+            Err(err) => Response::Error(err),
+        }
+    }
+}
+
+/// What the daemon is currently displaying, and the last value shown in each profile.
+#[derive(Default)]
+struct State {
+    visible: bool,
+    current: Option<(String, f64)>,
+    priority: Priority,
+    deadline: Option<Instant>,
+    last_values: BTreeMap<String, f64>,
+}
+
+impl State {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let buffer = fs::read_to_string(path).map_err(|err| Error::new("reading state", &err))?;
+        let last_values =
+            toml::from_str(&buffer).map_err(|err| Error::new("parsing state", &err))?;
+        Ok(State {
+            last_values,
+            ..State::default()
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(state_dir) = path.parent() {
+            fs::create_dir_all(state_dir)
+                .map_err(|err| Error::new("creating state directory", &err))?;
+        }
+        let buffer = toml::to_string(&self.last_values)
+            .map_err(|err| Error::new("serializing state", &err))?;
+        // The state is written to a temporary file first so that it is never left half-written.
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, buffer).map_err(|err| Error::new("writing state", &err))?;
+        fs::rename(&temp_path, path).map_err(|err| Error::new("writing state", &err))
+    }
+
+    /// Returns whether the last value of `profile` changed.
+    fn remember(&mut self, profile: &str, value: f64) -> bool {
+        self.last_values.insert(profile.to_owned(), value) != Some(value)
+    }
+
+    /// Returns whether the last value of `profile` changed.
+    fn show(&mut self, profile: &str, value: f64, priority: Priority, timeout: u64) -> bool {
+        self.visible = true;
+        self.current = Some((profile.to_owned(), value));
+        self.priority = priority;
+        self.deadline = match timeout {
+            0 => None,
+            timeout => Some(Instant::now() + Duration::from_millis(timeout)),
+        };
+        self.remember(profile, value)
+    }
+
+    fn hide(&mut self) {
+        self.visible = false;
+        self.deadline = None;
+    }
+
+    fn is_shadowed(&self, priority: Priority) -> bool {
+        self.visible && priority < self.priority
+    }
+
+    fn last_value(&self, profile: &str) -> Result<f64> {
+        self.last_values.get(profile).cloned().ok_or_else(|| {
+            Error::from_description(
+                "processing request",
+                format!("No value has been shown in color profile `{}`.", profile),
+            )
+        })
+    }
+
+    /// Resolves requests relative to the current state into absolute `Show` and `Hide` requests.
+    /// The resolved request is validated again because the remembered profile or value may no
+    /// longer be valid after the config is reloaded.
+    fn resolve_request(
+        &self,
+        profile_configs: &HashMap<String, config::ProfileConfig>,
+        request: Request,
+    ) -> Result<Request> {
+        validate_request(profile_configs, &request)?;
+        let request = match request {
+            Request::Adjust {
+                profile,
+                delta,
+                options,
+            } => {
+                let value = self.last_value(&profile)? + delta;
+                let global_config = &profile_configs[&profile].global_config;
+                Request::Show {
+                    value: value.clamp(global_config.min, global_config.max_value()),
+                    profile,
+                    options,
+                }
+            }
+            Request::Toggle { profile } => {
+                let current_profile = self.current.as_ref().map(|(profile, _)| profile);
+                if self.visible && (profile.is_none() || profile.as_ref() == current_profile) {
+                    return Ok(Request::Hide);
+                }
+                let profile = profile
+                    .or_else(|| current_profile.cloned())
+                    .ok_or_else(|| {
+                        Error::from_description("processing request", "No value has been shown.")
+                    })?;
+                Request::Show {
+                    value: self.last_value(&profile)?,
+                    profile,
+                    options: ShowOptions::default(),
+                }
+            }
+            request => request,
+        };
+        validate_request(profile_configs, &request)?;
+        Ok(request)
+    }
+
+    fn status(&self, config_path: &Path, start_time: Instant) -> Status {
+        Status {
+            pid: process::id(),
+            config_path: config_path.to_owned(),
+            visible: self.visible,
+            profile: self.current.as_ref().map(|(profile, _)| profile.clone()),
+            value: self.current.as_ref().map(|(_, value)| *value),
+            last_values: self.last_values.clone(),
+            uptime_secs: start_time.elapsed().as_secs(),
+        }
+    }
+}
+
+fn save_state(state: &State, state_path: Option<&Path>) {
+    if let Some(state_path) = state_path {
+        if let Err(err) = state.save(state_path) {
+            eprintln!("Error saving state: {}", err);
+        }
+    }
+}
+
+/// A notification sent to subscribers for everything the main loop handles.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Event {
+    Show { profile: String, value: f64 },
+    Hide,
+    Timeout,
+    Reload { error: Option<Error> },
+}
+
+/// A message forwarded to the main loop.
+enum Message {
+    /// A request along with where to send its response.
+    Request {
+        request: Request,
+        reply: Sender<Response>,
+    },
+    /// A connection that wants to receive every event.
+    Subscribe(Sender<Event>),
+}
+
+/// Returns the default socket path of the daemon for the current user and display, which is
+/// `$XDG_RUNTIME_DIR/robar/<DISPLAY>.sock`, or `/tmp/robar-<uid>/<DISPLAY>.sock` if
+/// `$XDG_RUNTIME_DIR` is not set.
+pub fn default_socket_path() -> PathBuf {
+    // Different screens of the same display share a daemon, so the screen number is dropped.
+    let display = env::var("DISPLAY").unwrap_or_else(|_| String::from("default"));
+    let display = match display.rfind(':') {
+        Some(colon) => match display[colon..].find('.') {
+            Some(dot) => &display[..colon + dot],
+            None => &display,
+        },
+        None => &display,
+    };
+
+    let socket_dir = match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(runtime_dir) if runtime_dir.is_absolute() => runtime_dir.join(env!("CARGO_PKG_NAME")),
+        _ => {
+            // Safe because `getuid` is always successful.
+            let uid = unsafe { libc::getuid() };
+            env::temp_dir().join(format!("{}-{}", env!("CARGO_PKG_NAME"), uid))
+        }
+    };
+
+    socket_dir.join(format!("{}.sock", display.replace('/', "_")))
+}
+
+/// Checks that `socket_dir` is a directory owned by the current user that no one else can access.
+fn check_socket_dir(socket_dir: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(socket_dir)
+        .map_err(|err| Error::new("creating socket directory", &err))?;
+    // Safe because `getuid` is always successful.
+    let uid = unsafe { libc::getuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o777 != SOCKET_DIR_MODE {
+        return Err(Error::from_description(
+            "creating socket directory",
+            format!(
+                "Expected `{}` to be a directory owned by the current user with mode {:o}.",
+                socket_dir.display(),
+                SOCKET_DIR_MODE,
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the path where the last value of each color profile is persisted, which is
+/// `$XDG_STATE_HOME/robar/state`, or `$HOME/.local/state/robar/state` if `$XDG_STATE_HOME` is not
+/// set.
+pub fn state_path() -> Option<PathBuf> {
+    config::xdg_dir("XDG_STATE_HOME")
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .map(|state_dir| state_dir.join(env!("CARGO_PKG_NAME")).join("state"))
+}
+
+/// Encodes `payload` as a frame prefixed with its length as a little-endian `u32`.
+pub fn encode_frame(payload: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(payload.len()).map_err(|err| Error::new("encoding frame", &err))?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Serializes `message` and writes it to `writer` as a single frame.
+pub fn write_message<T, W>(writer: &mut W, message: &T) -> Result<()>
+where
+    T: serde::Serialize,
+    W: Write,
+{
+    let payload = serialize(message).map_err(|err| Error::new("serializing message", &err))?;
+    writer
+        .write_all(&encode_frame(&payload)?)
+        .map_err(|err| Error::new("writing to socket", &err))?;
+    writer
+        .flush()
+        .map_err(|err| Error::new("flushing socket", &err))
+}
+
+/// Reassembles length-prefixed frames from a byte stream. A single read may contain several
+/// frames or only part of one, so any leftover bytes are kept for the next call.
+pub struct FrameBuffer {
+    buffer: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    fn next_frame(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+        let mut header = [0; FRAME_HEADER_SIZE];
+        header.copy_from_slice(&self.buffer[..FRAME_HEADER_SIZE]);
+        let frame_end = FRAME_HEADER_SIZE + u32::from_le_bytes(header) as usize;
+        if self.buffer.len() < frame_end {
+            return None;
+        }
+        let frame = self.buffer[FRAME_HEADER_SIZE..frame_end].to_vec();
+        self.buffer.drain(..frame_end);
+        Some(frame)
+    }
+
+    /// Returns the next complete frame, or `None` if the stream was closed between frames.
+    pub fn read_frame<R>(&mut self, reader: &mut R) -> Result<Option<Vec<u8>>>
+    where
+        R: Read,
+    {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            if let Some(frame) = self.next_frame() {
+                return Ok(Some(frame));
+            }
+            let len = match reader.read(&mut chunk) {
+                Ok(len) => len,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(Error::new("reading from socket", &err)),
+            };
+            if len == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(Error::from_description(
                     "reading from socket",
-                    "request body exceeded max request size",
-                )
-            })?;
-        match &mut self.pending {
-            Some((pending_start, pending_end, pending)) => {
-                let mut curr = vec![0; *pending_end - *pending_start + end_index];
-                let (left, right) = curr
-                    .as_mut_slice()
-                    .split_at_mut(*pending_end - *pending_start);
-                left.clone_from_slice(&pending[*pending_start..*pending_end]);
-                right.clone_from_slice(&self.next[0..end_index]);
-                std::mem::swap(pending, &mut self.next);
-                *pending_start = end_index + 1;
-                *pending_end = next_end;
-                Ok(Some(curr))
-            }
-            None => {
-                let mut curr = vec![0; end_index];
-                curr.clone_from_slice(&self.next[0..end_index]);
-                let new_buf = vec![0; MAX_REQUEST_SIZE];
-                let pending_buf = std::mem::replace(&mut self.next, new_buf);
-                self.pending = Some((end_index + 1, next_end, pending_buf));
-                Ok(Some(curr))
+                    "connection closed in the middle of a request",
+                ));
             }
+            self.buffer.extend_from_slice(&chunk[..len]);
         }
     }
 }
 
+/// Reads the next frame from `reader` and deserializes it, or returns `None` if the stream was
+/// closed between frames.
+pub fn read_message<T, R>(buffer: &mut FrameBuffer, reader: &mut R) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+    R: Read,
+{
+    match buffer.read_frame(reader)? {
+        Some(frame) => deserialize(&frame)
+            .map(Some)
+            .map_err(|err| Error::new("deserializing message", &err)),
+        None => Ok(None),
+    }
+}
+
 fn validate_request(
-    color_configs: &HashMap<String, config::ColorConfig>,
-    buffer: &[u8],
-) -> Result<Request> {
-    let request = deserialize(&buffer).map_err(|err| Error::new("deserializing request", &err))?;
-
-    if let Request::Show { ref profile, .. } = request {
-        if !color_configs.contains_key(profile) {
-            return Err(Error::from_description(
+    profile_configs: &HashMap<String, config::ProfileConfig>,
+    request: &Request,
+) -> Result<()> {
+    let profile = match request {
+        Request::Show { profile, .. } | Request::Adjust { profile, .. } => Some(profile),
+        Request::Toggle { profile } => profile.as_ref(),
+        _ => None,
+    };
+
+    let profile_config = match profile {
+        Some(profile) => profile_configs.get(profile).ok_or_else(|| {
+            Error::from_description(
                 "processing request",
                 format!("Color profile `{}` not found.", profile),
-            ));
+            )
+        })?,
+        None => return Ok(()),
+    };
+
+    let global_config = &profile_config.global_config;
+    match request {
+        Request::Show { value, .. } if !value.is_finite() => Err(Error::from_description(
+            "processing request",
+            "Expected `value` to be a finite number.",
+        )),
+        Request::Adjust { delta, .. } if !delta.is_finite() => Err(Error::from_description(
+            "processing request",
+            "Expected `delta` to be a finite number.",
+        )),
+        Request::Show { value, .. }
+            if *value < global_config.min || *value > global_config.max_value() =>
+        {
+            Err(Error::from_description(
+                "processing request",
+                format!(
+                    "Expected `value` in [{}, {}].",
+                    global_config.min,
+                    global_config.max_value(),
+                ),
+            ))
         }
+        _ => Ok(()),
     }
+}
+
+/// Writes `response` to `stream`. A client that does not wait for responses may close the
+/// connection before the request is handled, so a broken pipe is not an error.
+fn write_response(stream: &mut UnixStream, response: &Response) -> Result<()> {
+    let payload = serialize(response).map_err(|err| Error::new("serializing message", &err))?;
+    match stream.write_all(&encode_frame(&payload)?) {
+        Err(ref err) if err.kind() == ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(|err| Error::new("writing to socket", &err)),
+    }
+}
+
+fn handle_connection(stream: &mut UnixStream, tx: &Sender<Message>) -> Result<()> {
+    write_message(stream, &Hello::new())?;
+    let mut buffer = FrameBuffer::new();
+    while let Some(frame) = buffer.read_frame(stream)? {
+        let request = match deserialize(&frame) {
+            Ok(request) => request,
+            Err(err) => {
+                let err = Error::new("deserializing request", &err);
+                write_response(stream, &Response::Error(err))?;
+                continue;
+            }
+        };
+
+        // The daemon exits as soon as it handles `Stop`, so it is acknowledged up front.
+        if let Request::Stop = request {
+            write_response(stream, &Response::Ok)?;
+        }
+
+        // A subscription takes over the connection until the subscriber disconnects.
+        if let Request::Subscribe = request {
+            let (event_tx, event_rx) = channel();
+            tx.send(Message::Subscribe(event_tx))
+                .map_err(|err| Error::new("forwarding request", &err))?;
+            write_message(stream, &Response::Ok)?;
+            for event in event_rx {
+                write_message(stream, &event)?;
+            }
+            return Ok(());
+        }
+
+        let (reply_tx, reply_rx) = channel();
+        tx.send(Message::Request {
+            request,
+            reply: reply_tx,
+        })
+        .map_err(|err| Error::new("forwarding request", &err))?;
+        if let Ok(response) = reply_rx.recv() {
+            write_response(stream, &response)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends `event` to every subscriber, dropping the subscribers that have disconnected.
+fn broadcast(subscribers: &mut Vec<Sender<Event>>, event: &Event) {
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+fn send_reload(tx: &Sender<Message>) -> bool {
+    let (reply_tx, _) = channel();
+    tx.send(Message::Request {
+        request: Request::Reload,
+        reply: reply_tx,
+    })
+    .is_ok()
+}
 
-    Ok(request)
+fn watch_signals(tx: Sender<Message>) -> Result<()> {
+    let mut signals =
+        Signals::new([SIGHUP]).map_err(|err| Error::new("registering signal handler", &err))?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if !send_reload(&tx) {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+fn watch_config(config_path: &Path, tx: Sender<Message>) -> Result<()> {
+    // Editors commonly replace the file when saving, so the parent directory is watched instead
+    // of the file itself.
+    let config_name = config_path
+        .file_name()
+        .ok_or_else(|| Error::from_description("watching config", "Expected a config file."))?
+        .to_owned();
+    let config_dir = match config_path.parent() {
+        Some(config_dir) if config_dir != Path::new("") => config_dir,
+        _ => Path::new("."),
+    };
+    let mut inotify = Inotify::init().map_err(|err| Error::new("watching config", &err))?;
+    inotify
+        .watches()
+        .add(config_dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        .map_err(|err| Error::new("watching config", &err))?;
+
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let mut events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(err) => {
+                    eprintln!("Error watching config: {}", err);
+                    break;
+                }
+            };
+            if events.any(|event| event.name == Some(config_name.as_os_str())) && !send_reload(&tx)
+            {
+                break;
+            }
+        }
+    });
+    Ok(())
 }
 
 pub fn start_server(
     display: &mut display::Display,
-    global_config: &config::GlobalConfig,
-    color_configs: &HashMap<String, config::ColorConfig>,
+    socket_path: &Path,
+    config_path: &Path,
+    watch: bool,
+    mut profile_configs: HashMap<String, config::ProfileConfig>,
 ) -> Result<()> {
-    if Path::new(SOCKET_PATH).exists() {
-        fs::remove_file(SOCKET_PATH).map_err(|err| Error::new("removing existing socket", &err))?;
+    if let Some(socket_dir) = socket_path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(SOCKET_DIR_MODE)
+            .create(socket_dir)
+            .map_err(|err| Error::new("creating socket directory", &err))?;
+        // The default directory may be in a shared location such as `/tmp`, where another user
+        // could create it first to control the socket.
+        if default_socket_path().parent() == Some(socket_dir) {
+            check_socket_dir(socket_dir)?;
+        }
+    }
+    if socket_path.exists() {
+        fs::remove_file(socket_path).map_err(|err| Error::new("removing existing socket", &err))?;
     }
 
     let socket =
-        UnixListener::bind(SOCKET_PATH).map_err(|err| Error::new("binding socket", &err))?;
+        UnixListener::bind(socket_path).map_err(|err| Error::new("binding socket", &err))?;
     let (tx, rx) = channel();
+    let start_time = Instant::now();
+    let state_path = state_path();
+    let mut state = match state_path {
+        Some(ref state_path) => State::load(state_path).unwrap_or_else(|err| {
+            eprintln!("Error loading state, starting with empty state: {}", err);
+            State::default()
+        }),
+        None => State::default(),
+    };
+    let mut subscribers = Vec::new();
+
+    watch_signals(tx.clone())?;
+    if watch {
+        watch_config(config_path, tx.clone())?;
+    }
 
-    let color_configs_clone = color_configs.clone();
-    let tx_clone = tx.clone();
     thread::spawn(move || {
         for stream in socket.incoming() {
             let mut stream = match stream {
@@ -124,49 +692,490 @@ pub fn start_server(
                 }
             };
 
-            let color_configs_clone = color_configs_clone.clone();
-            let tx_clone = tx_clone.clone();
+            let tx = tx.clone();
             thread::spawn(move || {
-                let mut buffer = RequestBuffer::new();
-                while let Ok(Some(buffer)) = buffer.read_request(&mut stream) {
-                    match validate_request(&color_configs_clone, &buffer) {
-                        Ok(new_request) => tx_clone.send(new_request).unwrap(),
-                        Err(err) => eprintln!("Error with request: {}", err),
-                    }
+                if let Err(err) = handle_connection(&mut stream, &tx) {
+                    eprintln!("Error with connection: {}", err);
                 }
             });
         }
     });
 
     loop {
-        let request = if global_config.timeout != 0 {
-            let request = rx.recv_timeout(Duration::from_millis(global_config.timeout));
+        let message = match state.deadline {
+            Some(deadline) => {
+                let message = rx.recv_timeout(deadline.saturating_duration_since(Instant::now()));
 
-            match request {
-                Ok(request) => request,
-                Err(RecvTimeoutError::Timeout) => {
-                    display.hide();
-                    continue;
+                match message {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => {
+                        display.hide();
+                        state.hide();
+                        broadcast(&mut subscribers, &Event::Timeout);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
                 }
-                Err(RecvTimeoutError::Disconnected) => break,
             }
-        } else {
-            match rx.recv() {
-                Ok(request) => request,
+            None => match rx.recv() {
+                Ok(message) => message,
                 Err(RecvError) => break,
+            },
+        };
+        let (request, reply) = match message {
+            Message::Request { request, reply } => (request, reply),
+            Message::Subscribe(subscriber) => {
+                subscribers.push(subscriber);
+                continue;
             }
         };
 
-        match request {
+        let request = match state.resolve_request(&profile_configs, request) {
+            Ok(request) => request,
+            Err(err) => {
+                let _ = reply.send(Response::Error(err));
+                continue;
+            }
+        };
+
+        let result = match request {
             Request::Show {
                 ref profile,
-                ref value,
-            } => display.show(*value, &global_config, &color_configs[profile]),
-            Request::Hide => display.hide(),
+                value,
+                options,
+            } => {
+                // A shadowed request only updates the last value so that it is not lost.
+                let changed = if state.is_shadowed(options.priority) {
+                    state.remember(profile, value)
+                } else {
+                    let profile_config = &profile_configs[profile];
+                    display.show(
+                        value,
+                        state.last_values.get(profile).cloned(),
+                        &options,
+                        &profile_config.global_config,
+                        &profile_config.color_config,
+                    );
+                    let timeout = options
+                        .timeout
+                        .unwrap_or(profile_config.global_config.timeout);
+                    broadcast(
+                        &mut subscribers,
+                        &Event::Show {
+                            profile: profile.clone(),
+                            value,
+                        },
+                    );
+                    state.show(profile, value, options.priority, timeout)
+                };
+                if changed {
+                    save_state(&state, state_path.as_deref());
+                }
+                Ok(())
+            }
+            Request::Hide => {
+                display.hide();
+                state.hide();
+                broadcast(&mut subscribers, &Event::Hide);
+                Ok(())
+            }
             Request::Stop => break,
-            Request::Empty => {}
+            Request::Empty => Ok(()),
+            Request::Reload => {
+                display.clear_caches();
+                let result = match config::parse_config(config_path) {
+                    Ok(new_profile_configs) => {
+                        profile_configs = new_profile_configs;
+                        Ok(())
+                    }
+                    Err(err) => {
+                        eprintln!("Error reloading config, keeping previous config: {}", err);
+                        Err(err)
+                    }
+                };
+                broadcast(
+                    &mut subscribers,
+                    &Event::Reload {
+                        error: result.as_ref().err().cloned(),
+                    },
+                );
+                result
+            }
+            Request::Status => {
+                let _ = reply.send(Response::Status(state.status(config_path, start_time)));
+                continue;
+            }
+            Request::Subscribe => Ok(()),
+            Request::Adjust { .. } | Request::Toggle { .. } => {
+                unreachable!("Expected relative request to be resolved.")
+            }
+        };
+        let _ = reply.send(Response::from(result));
+    }
+
+    save_state(&state, state_path.as_deref());
+    fs::remove_file(socket_path).map_err(|err| Error::new("removing socket", &err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// A reader that returns at most the next of `chunk_sizes` bytes on each read.
+    struct ChunkedReader<I> {
+        data: Vec<u8>,
+        position: usize,
+        chunk_sizes: I,
+    }
+
+    impl<I> ChunkedReader<I> {
+        fn new(data: Vec<u8>, chunk_sizes: I) -> Self {
+            ChunkedReader {
+                data,
+                position: 0,
+                chunk_sizes,
+            }
         }
     }
 
-    Ok(())
+    impl<I> Read for ChunkedReader<I>
+    where
+        I: Iterator<Item = usize>,
+    {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let chunk_size = self.chunk_sizes.next().unwrap_or(usize::MAX).max(1);
+            let len = chunk_size
+                .min(buf.len())
+                .min(self.data.len() - self.position);
+            buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+            self.position += len;
+            Ok(len)
+        }
+    }
+
+    /// Returns an endless sequence of pseudo-random numbers from a xorshift generator.
+    fn random_numbers(seed: u64) -> impl Iterator<Item = u64> {
+        std::iter::successors(Some(seed), |state| {
+            let mut state = *state;
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            Some(state)
+        })
+        .skip(1)
+    }
+
+    fn encode_frames(payloads: &[&[u8]]) -> Vec<u8> {
+        payloads
+            .iter()
+            .flat_map(|payload| encode_frame(payload).unwrap())
+            .collect()
+    }
+
+    fn read_frames<R>(reader: &mut R) -> Vec<Vec<u8>>
+    where
+        R: Read,
+    {
+        let mut buffer = FrameBuffer::new();
+        let mut frames = Vec::new();
+        while let Some(frame) = buffer.read_frame(reader).unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn show_request(profile: &str, value: f64) -> Request {
+        Request::Show {
+            profile: profile.to_owned(),
+            value,
+            options: ShowOptions::default(),
+        }
+    }
+
+    fn assert_show_request(request: Request, expected_profile: &str, expected_value: f64) {
+        match request {
+            Request::Show { profile, value, .. } => {
+                assert_eq!(profile, expected_profile);
+                assert_eq!(value, expected_value);
+            }
+            request => panic!("Expected `show` request, but got `{}`.", request.kind()),
+        }
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_finite() {
+        let profile_configs = config::tests::parse("").unwrap();
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let request = show_request("volume", value);
+            assert!(validate_request(&profile_configs, &request).is_err());
+            let request = Request::Adjust {
+                profile: String::from("volume"),
+                delta: value,
+                options: ShowOptions::default(),
+            };
+            assert!(validate_request(&profile_configs, &request).is_err());
+        }
+        let request = show_request("volume", 50.0);
+        assert!(validate_request(&profile_configs, &request).is_ok());
+    }
+
+    #[test]
+    fn rejects_resolved_requests_after_reload() {
+        // `foo` was shown before a reload removed it and narrowed the range of `volume`.
+        let profile_configs = config::tests::parse("").unwrap();
+        let mut state = State::default();
+        state.show("foo", 30.0, Priority::Normal, 0);
+        state.hide();
+        state.remember("volume", 150.0);
+
+        assert!(validate_request(&profile_configs, &toggle_request(None)).is_ok());
+        assert!(state
+            .resolve_request(&profile_configs, toggle_request(None))
+            .is_err());
+        assert!(state
+            .resolve_request(&profile_configs, toggle_request(Some("volume")))
+            .is_err());
+    }
+
+    fn adjust_request(profile: &str, delta: f64) -> Request {
+        Request::Adjust {
+            profile: profile.to_owned(),
+            delta,
+            options: ShowOptions::default(),
+        }
+    }
+
+    fn toggle_request(profile: Option<&str>) -> Request {
+        Request::Toggle {
+            profile: profile.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn clamps_adjusted_values() {
+        let profile_configs = config::tests::parse("max_overflow = 150").unwrap();
+        let mut state = State::default();
+        state.remember("volume", 40.0);
+        let request = state.resolve_request(&profile_configs, adjust_request("volume", 5.0));
+        assert_show_request(request.unwrap(), "volume", 45.0);
+        let request = state.resolve_request(&profile_configs, adjust_request("volume", 500.0));
+        assert_show_request(request.unwrap(), "volume", 150.0);
+        let request = state.resolve_request(&profile_configs, adjust_request("volume", -500.0));
+        assert_show_request(request.unwrap(), "volume", 0.0);
+    }
+
+    #[test]
+    fn rejects_adjusting_without_a_value() {
+        let profile_configs = config::tests::parse("").unwrap();
+        let state = State::default();
+        assert!(state
+            .resolve_request(&profile_configs, adjust_request("volume", 5.0))
+            .is_err());
+        assert!(state
+            .resolve_request(&profile_configs, toggle_request(None))
+            .is_err());
+    }
+
+    #[test]
+    fn toggles_bar() {
+        let profile_configs = config::tests::parse(
+            r##"
+                [colors.brightness]
+                foreground = "#ffffff"
+                background = "#000000"
+                border = "#ffffff"
+            "##,
+        )
+        .unwrap();
+        let mut state = State::default();
+        state.remember("brightness", 80.0);
+        state.show("volume", 30.0, Priority::Normal, 0);
+
+        let request = state.resolve_request(&profile_configs, toggle_request(None));
+        assert!(matches!(request, Ok(Request::Hide)));
+        let request = state.resolve_request(&profile_configs, toggle_request(Some("volume")));
+        assert!(matches!(request, Ok(Request::Hide)));
+        let request = state.resolve_request(&profile_configs, toggle_request(Some("brightness")));
+        assert_show_request(request.unwrap(), "brightness", 80.0);
+
+        state.hide();
+        let request = state.resolve_request(&profile_configs, toggle_request(None));
+        assert_show_request(request.unwrap(), "volume", 30.0);
+        let request = state.resolve_request(&profile_configs, toggle_request(Some("brightness")));
+        assert_show_request(request.unwrap(), "brightness", 80.0);
+    }
+
+    #[test]
+    fn shadows_lower_priorities() {
+        let mut state = State::default();
+        assert!(!state.is_shadowed(Priority::Low));
+        state.show("volume", 30.0, Priority::High, 0);
+        assert!(state.is_shadowed(Priority::Low));
+        assert!(state.is_shadowed(Priority::Normal));
+        assert!(!state.is_shadowed(Priority::High));
+
+        // A shadowed request only updates the last value.
+        assert!(state.remember("brightness", 80.0));
+        assert_eq!(state.current, Some((String::from("volume"), 30.0)));
+        assert_eq!(state.last_values.get("brightness"), Some(&80.0));
+
+        state.hide();
+        assert!(!state.is_shadowed(Priority::Low));
+    }
+
+    #[test]
+    fn persists_bar_with_zero_timeout() {
+        let mut state = State::default();
+        state.show("volume", 30.0, Priority::Normal, 0);
+        assert!(state.visible);
+        assert_eq!(state.deadline, None);
+        state.show("volume", 30.0, Priority::Normal, 1000);
+        assert!(state.deadline.is_some());
+    }
+
+    #[test]
+    fn reads_frames_fed_one_byte_at_a_time() {
+        let data = encode_frames(&[b"first", b"", b"second"]);
+        let mut reader = ChunkedReader::new(data, std::iter::repeat(1));
+        assert_eq!(
+            read_frames(&mut reader),
+            vec![b"first".to_vec(), Vec::new(), b"second".to_vec()],
+        );
+    }
+
+    #[test]
+    fn reads_several_frames_in_one_read() {
+        let data = encode_frames(&[b"first", b"second", b"third"]);
+        let mut reader = ChunkedReader::new(data, std::iter::empty());
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            buffer.read_frame(&mut reader).unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert_eq!(reader.position, reader.data.len());
+        assert_eq!(
+            buffer.read_frame(&mut reader).unwrap(),
+            Some(b"second".to_vec())
+        );
+        assert_eq!(
+            buffer.read_frame(&mut reader).unwrap(),
+            Some(b"third".to_vec())
+        );
+        assert_eq!(buffer.read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_payloads_containing_carriage_return() {
+        let payload = [13, 13, 0, 13, 10];
+        let data = encode_frames(&[&payload, &[13]]);
+        let mut reader = ChunkedReader::new(data, std::iter::empty());
+        assert_eq!(read_frames(&mut reader), vec![payload.to_vec(), vec![13]]);
+    }
+
+    #[test]
+    fn round_trips_profile_names_longer_than_32_bytes() {
+        let profile = "a-color-profile-with-a-name-longer-than-32-bytes";
+        let mut data = Vec::new();
+        write_message(&mut data, &show_request(profile, 13.0)).unwrap();
+        let mut reader = ChunkedReader::new(data, std::iter::empty());
+        let request = read_message(&mut FrameBuffer::new(), &mut reader)
+            .unwrap()
+            .unwrap();
+        assert_show_request(request, profile, 13.0);
+    }
+
+    #[test]
+    fn reports_eof_in_the_middle_of_a_frame() {
+        let mut data = encode_frames(&[b"complete", b"truncated"]);
+        data.truncate(data.len() - 1);
+        let mut reader = ChunkedReader::new(data, std::iter::empty());
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            buffer.read_frame(&mut reader).unwrap(),
+            Some(b"complete".to_vec())
+        );
+        assert!(buffer.read_frame(&mut reader).is_err());
+    }
+
+    #[test]
+    fn reports_eof_in_the_middle_of_a_header() {
+        let mut reader = ChunkedReader::new(vec![1, 0], std::iter::empty());
+        assert!(FrameBuffer::new().read_frame(&mut reader).is_err());
+    }
+
+    #[test]
+    fn returns_none_on_eof_between_frames() {
+        let mut reader = ChunkedReader::new(Vec::new(), std::iter::empty());
+        assert_eq!(FrameBuffer::new().read_frame(&mut reader).unwrap(), None);
+
+        let data = encode_frames(&[b"only"]);
+        let mut reader = ChunkedReader::new(data, std::iter::empty());
+        assert_eq!(read_frames(&mut reader), vec![b"only".to_vec()]);
+    }
+
+    #[test]
+    fn checks_socket_dir_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let socket_dir = env::temp_dir().join(format!("robar-test-{}", process::id()));
+        fs::DirBuilder::new()
+            .mode(SOCKET_DIR_MODE)
+            .create(&socket_dir)
+            .unwrap();
+        fs::set_permissions(&socket_dir, fs::Permissions::from_mode(SOCKET_DIR_MODE)).unwrap();
+        assert!(check_socket_dir(&socket_dir).is_ok());
+        fs::set_permissions(&socket_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_socket_dir(&socket_dir).is_err());
+        fs::remove_dir(&socket_dir).unwrap();
+
+        let socket_file = env::temp_dir().join(format!("robar-test-{}.sock", process::id()));
+        fs::write(&socket_file, "").unwrap();
+        assert!(check_socket_dir(&socket_file).is_err());
+        fs::remove_file(&socket_file).unwrap();
+    }
+
+    #[test]
+    fn ignores_client_closing_before_response() {
+        let (mut stream, client) = UnixStream::pair().unwrap();
+        drop(client);
+        assert!(write_response(&mut stream, &Response::Ok).is_ok());
+    }
+
+    #[test]
+    fn round_trips_requests_with_random_chunking() {
+        let mut numbers = random_numbers(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100 {
+            let requests = (0..1 + numbers.next().unwrap() % 10)
+                .map(|_| {
+                    let length = (numbers.next().unwrap() % 100) as usize;
+                    let profile = (0..length)
+                        .map(|_| char::from(b' ' + (numbers.next().unwrap() % 95) as u8))
+                        .collect::<String>();
+                    let value = (numbers.next().unwrap() % 1000) as f64 / 10.0;
+                    (profile, value)
+                })
+                .collect::<Vec<_>>();
+
+            let mut data = Vec::new();
+            for (profile, value) in &requests {
+                write_message(&mut data, &show_request(profile, *value)).unwrap();
+            }
+            let chunk_sizes = numbers
+                .by_ref()
+                .map(|number| (number % 64) as usize + 1)
+                .take(data.len())
+                .collect::<Vec<_>>();
+            let mut reader = ChunkedReader::new(data, chunk_sizes.into_iter());
+
+            let mut buffer = FrameBuffer::new();
+            for (profile, value) in &requests {
+                let request = read_message(&mut buffer, &mut reader).unwrap().unwrap();
+                assert_show_request(request, profile, *value);
+            }
+            assert!(read_message::<Request, _>(&mut buffer, &mut reader)
+                .unwrap()
+                .is_none());
+        }
+    }
 }
@@ -0,0 +1,85 @@
+use crate::{Error, Result};
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use std::fs;
+use std::process::Command;
+
+/// The coverage of rendered text, where each value is in `[0, 1]`.
+#[derive(Clone)]
+pub struct Bitmap {
+    pub width: u16,
+    pub height: u16,
+    pub coverage: Vec<f32>,
+}
+
+/// Loads the font that best matches the fontconfig pattern `name` (e.g. `DejaVu Sans:bold`).
+pub fn find_font(name: &str) -> Result<FontVec> {
+    let output = Command::new("fc-match")
+        .arg("--format=%{file}\n%{index}")
+        .arg(name)
+        .output()
+        .map_err(|err| Error::new(format!("finding font `{}`", name), &err))?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output.lines();
+    let path = match lines.next() {
+        Some(path) if !path.is_empty() => path,
+        _ => {
+            return Err(Error::from_description(
+                format!("finding font `{}`", name),
+                "Expected fontconfig to match a font.",
+            ))
+        }
+    };
+    let index = lines
+        .next()
+        .and_then(|index| index.parse().ok())
+        .unwrap_or(0);
+
+    let buffer =
+        fs::read(path).map_err(|err| Error::new(format!("reading font `{}`", path), &err))?;
+    FontVec::try_from_vec_and_index(buffer, index)
+        .map_err(|err| Error::new(format!("parsing font `{}`", path), &err))
+}
+
+/// Renders `text` on a single line with `font` at `size` pixels.
+pub fn render(font: &FontVec, size: f32, text: &str) -> Bitmap {
+    let scale = PxScale::from(size);
+    let scaled_font = font.as_scaled(scale);
+
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous_id = None;
+    for c in text.chars() {
+        let id = scaled_font.glyph_id(c);
+        if let Some(previous_id) = previous_id {
+            caret += scaled_font.kern(previous_id, id);
+        }
+        glyphs.push(id.with_scale_and_position(scale, point(caret, scaled_font.ascent())));
+        caret += scaled_font.h_advance(id);
+        previous_id = Some(id);
+    }
+
+    let width = caret.ceil() as u16;
+    let height = scaled_font.height().ceil() as u16;
+    let mut coverage = vec![0.0; usize::from(width) * usize::from(height)];
+    for glyph in glyphs {
+        let outlined_glyph = match font.outline_glyph(glyph) {
+            Some(outlined_glyph) => outlined_glyph,
+            None => continue,
+        };
+        let bounds = outlined_glyph.px_bounds();
+        outlined_glyph.draw(|x, y, glyph_coverage| {
+            let x = bounds.min.x as i32 + x as i32;
+            let y = bounds.min.y as i32 + y as i32;
+            if 0 <= x && x < i32::from(width) && 0 <= y && y < i32::from(height) {
+                let index = y as usize * usize::from(width) + x as usize;
+                coverage[index] = f32::min(coverage[index] + glyph_coverage, 1.0);
+            }
+        });
+    }
+
+    Bitmap {
+        width,
+        height,
+        coverage,
+    }
+}